@@ -0,0 +1,248 @@
+use crate::errors::*;
+use crate::linker::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+// libraries written by `vml ar`: objects bundled together with an index of
+// the labels each one defines. the linker only takes the objects that define
+// something the program uses.
+//
+// archives are plain text:
+//     VMLAR 1
+//     I <name> <member>          `name` is defined by member number `member`
+//     F <member name> <lines>    followed by the member's object, `lines` long
+
+const MAGIC: &str = "VMLAR 1";
+
+pub struct Archive {
+    pub name: String,
+    pub members: Vec<Object>,
+    pub index: HashMap<String, usize>
+}
+
+fn fail(error: String) -> ! {
+    format_errora(error);
+    process::exit(1);
+}
+
+impl Archive {
+    pub fn new(name: &str, members: Vec<Object>) -> Self {
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for (member, object) in members.iter().enumerate() {
+            for (symbol, _) in &object.globals {
+                if let Some(other) = index.insert(symbol.clone(), member) {
+                    fail(format!("`{}` is defined in both `{}` and `{}`.", symbol, members[other].name, object.name));
+                }
+            }
+        }
+        Archive { name: name.to_string(), members, index }
+    }
+
+    pub fn to_text(self: &Archive) -> String {
+        let mut output: String = format!("{}\n", MAGIC);
+        let mut index: Vec<(&String, &usize)> = self.index.iter().collect();
+        index.sort();
+        for (symbol, member) in index {
+            output += &format!("I {} {}\n", symbol, member);
+        }
+        for object in &self.members {
+            let text = object.to_text();
+            output += &format!("F {} {}\n", object.name, text.lines().count());
+            output += &text;
+        }
+        output
+    }
+
+    // None if `text` is not an archive. members are named `archive(member)`.
+    pub fn from_text(name: &str, text: &str) -> Option<Self> {
+        let mut entries = text.lines();
+        if entries.next()? != MAGIC {
+            return None;
+        }
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut members: Vec<Object> = Vec::new();
+        while let Some(entry) = entries.next() {
+            let fields: Vec<&str> = entry.split(' ').collect();
+            match fields[..] {
+                ["I", symbol, member] => { index.insert(symbol.to_string(), member.parse().ok()?); },
+                ["F", member, lines] => {
+                    let lines = lines.parse::<usize>().ok()?;
+                    let text: Vec<&str> = entries.by_ref().take(lines).collect();
+                    if text.len() != lines { return None; }
+                    members.push(Object::from_text(&format!("{}({})", name, member), &text.join("\n"))?);
+                },
+                _ => return None
+            }
+        }
+        if index.values().any(|member| *member >= members.len()) {
+            return None;
+        }
+        Some(Archive { name: name.to_string(), members, index })
+    }
+}
+
+// a file given to the linker or to `import`, either an object or an archive.
+pub enum Library {
+    Object(Object),
+    Archive(Archive)
+}
+
+impl Library {
+    pub fn load(filename: &str) -> Self {
+        let text = match fs::read_to_string(filename) {
+            Ok(text) => text,
+            Err(why) => fail(format!("Couldn't read `{}`: {}", filename, why))
+        };
+        if let Some(archive) = Archive::from_text(filename, &text) {
+            return Library::Archive(archive);
+        }
+        match Object::from_text(filename, &text) {
+            Some(object) => Library::Object(object),
+            None => fail(format!("`{}` is not a VML object file or archive.", filename))
+        }
+    }
+
+    pub fn objects(self: &Library) -> &[Object] {
+        match self {
+            Library::Object(object) => std::slice::from_ref(object),
+            Library::Archive(archive) => &archive.members
+        }
+    }
+}
+
+// bundles objects into an archive. members are named after their file.
+pub fn archive(name: &str, filenames: &[String]) -> Archive {
+    let members: Vec<Object> = filenames.iter()
+        .map(|filename| {
+            let mut object = Object::load(filename);
+            object.name = Path::new(filename).file_name().map_or(filename.clone(), |n| n.to_string_lossy().to_string());
+            object
+        })
+        .collect();
+    Archive::new(name, members)
+}
+
+// every object given, followed by the archive members that define labels
+// those use (or `main`), and the members those use in turn. archives are
+// searched in the order they were given.
+pub fn select(libraries: &[Library]) -> Vec<Object> {
+    let mut selected: Vec<Object> = Vec::new();
+    for library in libraries {
+        if let Library::Object(object) = library {
+            selected.push(object.clone());
+        }
+    }
+    let archives: Vec<&Archive> = libraries.iter()
+        .filter_map(|library| match library {
+            Library::Archive(archive) => Some(archive),
+            _ => None
+        })
+        .collect();
+
+    let mut taken: Vec<(usize, usize)> = Vec::new();
+    loop {
+        let defined: Vec<&str> = selected.iter()
+            .flat_map(|object| object.globals.iter().map(|(name, _)| name.as_str()))
+            .collect();
+        let mut undefined: Vec<String> = selected.iter()
+            .flat_map(|object| object.imports())
+            .chain(["main"])
+            .filter(|name| !defined.contains(name))
+            .map(String::from)
+            .collect();
+        undefined.sort();
+        undefined.dedup();
+
+        let mut added: Vec<Object> = Vec::new();
+        for name in &undefined {
+            let found = archives.iter().enumerate()
+                .find_map(|(a, archive)| archive.index.get(name).map(|member| (a, *member)));
+            if let Some((a, member)) = found {
+                if !taken.contains(&(a, member)) {
+                    taken.push((a, member));
+                    added.push(archives[a].members[member].clone());
+                }
+            }
+        }
+        if added.is_empty() {
+            return selected;
+        }
+        selected.extend(added);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an object defining `defines` and using `uses`
+    fn object(name: &str, defines: &[&str], uses: &[&str]) -> Object {
+        let mut object = Object::new(name);
+        object.code = vec![0; 4 * uses.len().max(1)];
+        for (index, symbol) in defines.iter().enumerate() {
+            object.globals.push((symbol.to_string(), index));
+        }
+        for (index, symbol) in uses.iter().enumerate() {
+            object.relocations.push(Relocation { offset: index * 4, width: 4, target: Target::Symbol(symbol.to_string()) });
+        }
+        object
+    }
+
+    fn names(objects: &[Object]) -> Vec<&str> {
+        objects.iter().map(|object| object.name.as_str()).collect()
+    }
+
+    #[test]
+    fn selects_only_members_that_are_used() {
+        let library = Archive::new("lib.a", vec![
+            object("print.o", &["print"], &["write"]),
+            object("write.o", &["write"], &[]),
+            object("unused.o", &["unused"], &["print"]),
+            object("math.o", &["square", "cube"], &[])
+        ]);
+        let main = object("main.o", &["main"], &["print", "square"]);
+        let selected = select(&[Library::Object(main), Library::Archive(library)]);
+        assert_eq!(names(&selected), vec!["main.o", "print.o", "math.o", "write.o"]);
+    }
+
+    #[test]
+    fn selects_main_from_an_archive() {
+        let library = Archive::new("lib.a", vec![
+            object("helper.o", &["helper"], &[]),
+            object("main.o", &["main"], &["helper"])
+        ]);
+        assert_eq!(names(&select(&[Library::Archive(library)])), vec!["main.o", "helper.o"]);
+    }
+
+    #[test]
+    fn earlier_archives_win() {
+        let first = Archive::new("first.a", vec![object("first.o", &["print"], &[])]);
+        let second = Archive::new("second.a", vec![object("second.o", &["print"], &[])]);
+        let main = object("main.o", &["main"], &["print"]);
+        let selected = select(&[Library::Object(main), Library::Archive(first), Library::Archive(second)]);
+        assert_eq!(names(&selected), vec!["main.o", "first.o"]);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let library = Archive::new("lib.a", vec![
+            object("print.o", &["print"], &["write"]),
+            object("write.o", &["write", "flush"], &[])
+        ]);
+        let text = library.to_text();
+        let read = Archive::from_text("lib.a", &text).unwrap();
+        // members are named after the archive they were read from
+        assert_eq!(names(&read.members), vec!["lib.a(print.o)", "lib.a(write.o)"]);
+        for (member, original) in read.members.iter().zip(&library.members) {
+            assert_eq!(member.to_text(), original.to_text());
+        }
+        assert_eq!(read.index.get("flush"), Some(&1));
+
+        assert!(Archive::from_text("lib.a", "VMLOBJ 1\n").is_none());
+        assert!(Archive::from_text("lib.a", "VMLAR 1\nI print 3\n").is_none());
+        assert!(Archive::from_text("lib.a", "VMLAR 1\nF print.o 5\nVMLOBJ 1\n").is_none());
+    }
+}
@@ -2,24 +2,409 @@ use crate::token::*;
 use crate::errors::*;
 use crate::variable::*;
 use crate::util::*;
+use crate::debug_info::*;
+use crate::macros::*;
+use crate::expression::*;
+use crate::listing::*;
+use crate::linker::*;
+use crate::archive::*;
 
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::u64;
-use std::u32;
 use std::u8;
 use std::process;
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 
+// VML keywords, which can not name a method or a constant.
+const RESERVED_WORDS: [&str; 35] = [
+    "if", "and", "or", "while", "not", "root", "pow", "return", "dup", "swap", "drop", "rot",
+    "memory", "syscall", "let", "const", "method", "extern", "copy", "alloc", "free", "spawn",
+    "yield", "join", "thread", "try", "catch", "throw", "vector", "interrupt", "iret",
+    "atomic-cas", "atomic-add", "!atomic", "@atomic"
+];
+
 pub struct Lexer {
     tokens: Vec<Token>,
     toks: String,
     expr: String,
     lexer_state: u16,
-    expected: i8
+    expected: i8,
+    line: usize,
+    source: Vec<SourceLine>,
+    file_lines: Vec<String>,
+    listing: Option<String>,
+    map: Option<String>,
+    // the global label that `..name` labels belong to
+    scope: String,
+    // how many times each numeric label (`1:`) has been defined so far
+    numeric: HashMap<String, usize>,
+    forward: Vec<(String, usize, usize)>,
+    defined: HashMap<String, usize>,
+    output: String,
+    // write a relocatable object rather than a binary
+    object: bool,
+    // objects and archives given to `import`
+    imports: Vec<String>,
+    // constants given with `-D NAME=VALUE`
+    defines: Vec<(String, String)>,
+    // the line number and text of each line of a VML program, after includes
+    vml_source: Vec<(usize, String)>,
+    // the line of `vml_source` each line of generated assembly came from
+    vml_lines: Vec<Option<usize>>
+}
+
+// splits the operands of a directive on commas, leaving commas inside quotes
+// alone and stopping at a comment.
+pub fn split_operands(text: &str) -> Vec<String> {
+    let mut operands: Vec<String> = Vec::new();
+    let mut current: String = String::new();
+    let mut quote: Option<char> = None;
+    let mut escape = false;
+    for c in text.chars() {
+        match quote {
+            Some(q) => {
+                if !escape && c == q { quote = None; }
+                escape = !escape && c == '\\';
+            },
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == ';' => break,
+            None if c == ',' => {
+                operands.push(current.trim().to_string());
+                current = String::new();
+                continue;
+            },
+            None => ()
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+// reads an integer literal: decimal, 0x hexadecimal, 0b binary, negative
+// (stored as its two's complement) or a quoted character.
+pub fn parse_literal(text: &str) -> Option<u64> {
+    if let Some(negative) = text.strip_prefix('-') {
+        let value = parse_literal(negative)?;
+        if value > i64::MAX as u64 + 1 { return None; }
+        return Some(value.wrapping_neg());
+    }
+    if let Some(hex) = text.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok();
+    }
+    if let Some(binary) = text.strip_prefix("0b") {
+        return u64::from_str_radix(binary, 2).ok();
+    }
+    if text.len() >= 3 && text.starts_with('\'') && text.ends_with('\'') {
+        let inner = &text[1..text.len() - 1];
+        return match inner {
+            "\\n" => Some(0x0a),
+            "\\t" => Some(0x09),
+            "\\r" => Some(0x0d),
+            "\\0" => Some(0x00),
+            "\\\\" => Some(0x5c),
+            "\\'" => Some(0x27),
+            _ if inner.chars().count() == 1 => Some(inner.chars().next()? as u64),
+            _ => None
+        };
+    }
+    text.parse::<u64>().ok()
+}
+
+// whether `value` can be stored in `width` bytes, either as an unsigned
+// number or as a negative one.
+fn fits_width(value: u64, width: usize) -> bool {
+    if width >= 8 { return true; }
+    let bits = width * 8;
+    let signed = value as i64;
+    value < (1 << bits) || (signed < 0 && signed >= -(1i64 << (bits - 1)))
+}
+
+// the prefix an INTEGER token uses for each width.
+fn width_prefix(width: usize) -> char {
+    match width {
+        1 => 'B',
+        2 => 'W',
+        4 => 'U',
+        _ => 'L'
+    }
+}
+
+// the number of bytes of operands each instruction takes, with both
+// registers in one byte.
+fn operand_bytes(mnemonic: &str) -> Option<i8> {
+    let bytes = match mnemonic {
+        "mov" => 9,
+        "ldr" => 5,
+        "indl" => 9,
+        "cpy" => 1,
+        "str" => 5,
+        "inds" => 5,
+        "push" => 1,
+        "pop" => 1,
+        "iadd" => 1,
+        "isub" => 1,
+        "imul" => 1,
+        "idiv" => 1,
+        "dadd" => 1,
+        "dsub" => 1,
+        "dmul" => 1,
+        "ddiv" => 1,
+        "icst" => 1,
+        "dcst" => 1,
+        "shl" => 1,
+        "shr" => 1,
+        "and" => 1,
+        "or" => 1,
+        "neg" => 1,
+        "icmp" => 1,
+        "dcmp" => 1,
+        "jmp" => 4,
+        "beq" => 4,
+        "bne" => 4,
+        "bgt" => 4,
+        "blt" => 4,
+        "jsr" => 4,
+        "ret" => 0,
+        "sys" => 4,
+        "halt" => 0,
+        "adr" => 5,
+        "lei" => 1,
+        "lst" => 1,
+        "ltt" => 1,
+        "lsf" => 1,
+        "sei" => 1,
+        "sst" => 1,
+        "stt" => 1,
+        "ssf" => 1,
+        "bufc" => 1,
+        "bseq" => 1,
+        "lseq" => 1,
+        "pow" => 1,
+        "root" => 1,
+        "call" => 1,
+        "ald" => 1,
+        "ast" => 1,
+        "cas" => 1,
+        "xadd" => 1,
+        "try" => 4,
+        "endtry" => 0,
+        "throw" => 1,
+        "int" => 1,
+        "iret" => 0,
+        _ => return None
+    };
+    Some(bytes)
+}
+
+// the width of the immediate an instruction takes, if any. it follows a
+// register for the 5 and 9 byte forms.
+fn immediate_bytes(mnemonic: &str) -> Option<usize> {
+    match operand_bytes(mnemonic)? {
+        9 => Some(8),
+        4 | 5 => Some(4),
+        _ => None
+    }
+}
+
+// a decimal number with a fraction or exponent, eg. `1.5`, `-2e3`.
+fn parse_float(text: &str) -> Option<f64> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) || !digits.contains(['.', 'e', 'E']) || digits.starts_with("0x") {
+        return None;
+    }
+    text.parse::<f64>().ok()
+}
+
+pub fn is_register(text: &str) -> bool {
+    text.strip_prefix('r').is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+// splits off a label defined at the start of a line (`.name: ...`).
+fn split_label(text: &str) -> (Option<&str>, &str) {
+    let rest = text.trim_start();
+    if let Some((name, after)) = rest.strip_prefix('.').and_then(|r| r.split_once(':')) {
+        if !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == '"') {
+            return (Some(name), after.trim_start());
+        }
+    }
+    (None, rest)
+}
+
+// where labels and the static memory area are while evaluating. objects
+// are evaluated with each of them moved in turn, to find out which one a
+// value depends on and so how the linker has to patch it.
+#[derive(Clone, Copy)]
+struct Placement {
+    code: u64,
+    memory: u64,
+    // what labels defined in another object read as; None when every label
+    // has to be defined in this file
+    imports: Option<u64>
+}
+
+const FLAT: Placement = Placement { code: 0, memory: 0, imports: None };
+
+// evaluates an expression left by the lexer, looking `.equ` names up in
+// `constants`. `visiting` holds the names being evaluated, to catch a
+// constant defined in terms of itself, and `imported` collects the labels
+// read as imports.
+fn evaluate_operand(text: &str, labels: &HashMap<String, usize>, constants: &HashMap<String, String>,
+                    placement: Placement, imported: &RefCell<Vec<String>>, visiting: &mut Vec<String>) -> Result<u64, String> {
+    let mut label = |name: &str| match labels.get(name) {
+        Some(addr) => Some((*addr as u64).wrapping_add(placement.code)),
+        None => {
+            imported.borrow_mut().push(name.to_string());
+            placement.imports
+        }
+    };
+    evaluate(text, &mut label, &mut |name| {
+        let definition = match constants.get(name) {
+            Some(definition) => definition,
+            // the static `memory` area starts at 0 unless an object is linked
+            None if name == "__memory" => return Ok(placement.memory),
+            None => return Err(format!("`{}` is not defined. (constants are defined with `.equ <name> <value>`)", name))
+        };
+        if visiting.iter().any(|v| v == name) {
+            return Err(format!("`{}` is defined in terms of itself.", name));
+        }
+        visiting.push(name.to_string());
+        let value = evaluate_operand(definition, labels, constants, placement, imported, visiting);
+        visiting.pop();
+        value
+    })
+}
+
+// evaluates an expression in an object, along with what the linker has to
+// add to it: the address of the object's code or memory, or of a label in
+// another object.
+fn relocate_operand(text: &str, labels: &HashMap<String, usize>,
+                    constants: &HashMap<String, String>) -> Result<(u64, Option<Target>), String> {
+    // odd, so that masks and shifts of an address do not look unmoved
+    const MOVED: u64 = 0x1_0000_0001;
+    let imported: RefCell<Vec<String>> = RefCell::new(Vec::new());
+    let at = |placement: Placement| evaluate_operand(text, labels, constants, placement, &imported, &mut Vec::new());
+    let value = at(Placement { code: 0, memory: 0, imports: Some(0) })?;
+    let code = at(Placement { code: MOVED, memory: 0, imports: Some(0) })?.wrapping_sub(value);
+    let memory = at(Placement { code: 0, memory: MOVED, imports: Some(0) })?.wrapping_sub(value);
+    let import = at(Placement { code: 0, memory: 0, imports: Some(MOVED) })?.wrapping_sub(value);
+
+    let mut names = imported.into_inner();
+    names.sort();
+    names.dedup();
+    let unlinkable = Err(format!("`{}` cannot be linked; it has to be a single address plus or minus a constant.", text));
+    if [code, memory, import].iter().any(|moved| *moved != 0 && *moved != MOVED)
+        || [code, memory, import].iter().filter(|moved| **moved == MOVED).count() > 1
+        || names.len() > 1 || (names.len() == 1 && import != MOVED) {
+        return unlinkable;
+    }
+    let target = if code == MOVED {
+        Some(Target::Code)
+    } else if memory == MOVED {
+        Some(Target::Memory)
+    } else {
+        names.pop().map(Target::Symbol)
+    };
+    Ok((value, target))
+}
+
+// the value of an expression made of numbers and `.equ` constants alone, as
+// used by `.if`.
+pub fn evaluate_constant(text: &str, constants: &HashMap<String, String>) -> Result<u64, String> {
+    evaluate_operand(text, &HashMap::new(), constants, FLAT, &RefCell::new(Vec::new()), &mut Vec::new())
+}
+
+// the operand of `.zero`, `.align` and `.memory`. alignments have to be a
+// power of two.
+fn parse_count(directive: &str, operands: &[String]) -> Result<u64, String> {
+    match parse_literal(&operands[0]) {
+        Some(value) if operands.len() == 1 && directive == ".align" && !value.is_power_of_two() =>
+            Err(format!("`.align` takes a power of two, found {}.", value)),
+        Some(value) if operands.len() == 1 && value > 0 => Ok(value),
+        _ => Err(format!("`{}` takes a single positive count.", directive))
+    }
+}
+
+fn is_numeric_reference(text: &str) -> bool {
+    text.len() > 1 && text.ends_with(['b', 'f']) && text[..text.len() - 1].chars().all(|c| c.is_ascii_digit())
+}
+
+// splits off a numeric label defined at the start of a line (`1: ...`).
+fn split_numeric_label(text: &str) -> Option<(&str, &str)> {
+    let (number, rest) = text.trim_start().split_once(':')?;
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((number, rest))
+}
+
+// rewrites `..name` in an expression as `scope..name`.
+fn scope_expression(text: &str, scope: &str) -> String {
+    let mut output: String = String::new();
+    let mut previous: Option<char> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let starts_label = previous.is_none_or(|p| !(p.is_alphanumeric() || "_@.-".contains(p)));
+        if c == '.' && chars.peek() == Some(&'.') && starts_label {
+            output += ".";
+            output += scope;
+        }
+        output.push(c);
+        previous = Some(c);
+    }
+    output
+}
+
+// writes a program and its debug information next to it.
+pub fn write_binary(filename: &str, binary: &[u8], debug_info: &DebugInfo) {
+    let mut file = match File::create(filename) {
+        Err(why) => panic!("Couldn't create file {}: {}", filename, why),
+        Ok(file) => file
+    };
+    match file.write_all(binary) {
+        Err(why) => panic!("Couldn't write to file {}: {}", filename, why),
+        Ok(_) => ()
+    }
+    debug_info.write(&Path::new(filename).with_extension("sym").to_string_lossy());
+}
+
+// escapes are resolved here rather than in the lexer, so `.ascii` and
+// string literals share them.
+fn push_string(file_vec: &mut Vec<String>, data: &str) {
+    let mut escape = false;
+    for chars in data.chars() {
+        if chars == '\\' && !escape {
+            escape = true;
+        }
+        else if !escape { file_vec.push(format!("{:x}", chars as u8)); }
+        else {
+            match chars {
+                'n' => file_vec.push("0a".to_string()),
+                't' => file_vec.push("09".to_string()),
+                'v' => file_vec.push("0b".to_string()),
+                '"' => file_vec.push("22".to_string()),
+                'r' => file_vec.push("0d".to_string()),
+                'f' => file_vec.push("0c".to_string()),
+                'b' => file_vec.push("08".to_string()),
+                'a' => file_vec.push("07".to_string()),
+                'e' => file_vec.push("1b".to_string()),
+                '\'' => file_vec.push("27".to_string()),
+                '\\' => file_vec.push("5c".to_string()),
+                _ => {
+                    format_errora(format!("Unknown escape sequence found! ('\\{}')", chars));
+                    process::exit(1);
+                }
+            }
+            escape = false;
+        }
+    }
 }
 
 impl Lexer {
@@ -30,6 +415,21 @@ impl Lexer {
             expr: String::new(),
             lexer_state: 0,
             expected: 0,
+            line: 0,
+            source: Vec::new(),
+            file_lines: Vec::new(),
+            listing: None,
+            map: None,
+            scope: String::new(),
+            numeric: HashMap::new(),
+            forward: Vec::new(),
+            defined: HashMap::new(),
+            output: String::from("out.bin"),
+            object: false,
+            imports: Vec::new(),
+            defines: Vec::new(),
+            vml_source: Vec::new(),
+            vml_lines: Vec::new(),
         }
     }
 
@@ -42,11 +442,92 @@ impl Lexer {
     pub fn set_method_bit(self: &mut Lexer) { self.lexer_state = self.lexer_state | (1 << 5); }
     pub fn set_variable_bit(self: &mut Lexer) { self.lexer_state = self.lexer_state | (1 << 6); }
 
+    pub fn set_listing(self: &mut Lexer, filename: String) {
+        self.listing = Some(filename);
+    }
+
+    pub fn set_map(self: &mut Lexer, filename: String) {
+        self.map = Some(filename);
+    }
+
+    pub fn set_output(self: &mut Lexer, filename: String) {
+        self.output = filename;
+    }
+
+    pub fn define(self: &mut Lexer, name: String, value: String) {
+        self.defines.push((name, value));
+    }
+
+    pub fn set_object(self: &mut Lexer) {
+        self.object = true;
+    }
+
+    // programs that `import` a library are compiled as an object and linked
+    // with it.
+    fn relocatable(self: &Lexer) -> bool {
+        self.object || !self.imports.is_empty()
+    }
+
     pub fn add_token(self: &mut Lexer, t: TokenType, d: &str) {
-        self.tokens.push(Token::new(t, String::from(d)));
+        if t == TokenType::REGISTER && !d.parse::<u8>().is_ok_and(|n| n < 16) {
+            self.fail_at(self.line, format!("`r{}` is not a register. (there are 16, r0 to r15)", d));
+        }
+        let d = if t == TokenType::LABEL { self.scope_label(d) } else { String::from(d) };
+        self.tokens.push(Token::new(t, d, self.line));
         self.toks = String::from("");
     }
 
+    // `..name` belongs to the global label before it, and is renamed to
+    // `global..name`. labels from macros and numeric labels (holding an `@`)
+    // do not start a scope of their own.
+    fn scope_label(self: &mut Lexer, data: &str) -> String {
+        let (kind, name) = data.split_at(1);
+        match kind {
+            "D" => {
+                let name = if name.starts_with('.') { format!("{}.{}", self.scope, name) } else { name.to_string() };
+                if !name.contains('@') && !name.contains("..") {
+                    self.scope = name.clone();
+                }
+                if let Some(previous) = self.defined.insert(name.clone(), self.line) {
+                    let previous = self.source[previous].file_line() + 1;
+                    self.fail_at(self.line, format!("Label '{}' is already defined on line {}.", name, previous));
+                }
+                format!("D{}", name)
+            },
+            "U" if name.starts_with('.') => format!("U{}.{}", self.scope, name),
+            "E" => {
+                // E<width>:<line>:<expression>, with labels inside the expression
+                let mut fields = name.splitn(3, ':');
+                let (width, line) = (fields.next().unwrap(), fields.next().unwrap());
+                format!("E{}:{}:{}", width, line, scope_expression(fields.next().unwrap(), &self.scope))
+            },
+            _ => data.to_string()
+        }
+    }
+
+    // numeric labels (`1:`) can be defined any number of times; `1b` refers
+    // to the closest one before and `1f` to the closest one after.
+    fn define_numeric_label(self: &mut Lexer, number: &str) {
+        let count = self.numeric.entry(number.to_string()).or_insert(0);
+        let name = format!("D{}@{}", number, count);
+        *count += 1;
+        self.add_token(TokenType::LABEL, &name);
+    }
+
+    fn numeric_reference(self: &mut Lexer, reference: &str, line: usize) -> String {
+        let (number, direction) = reference.split_at(reference.len() - 1);
+        let count = self.numeric.get(number).copied().unwrap_or(0);
+        if direction == "b" {
+            if count == 0 {
+                self.fail_at(line, format!("`{}` has no `{}:` before it.", reference, number));
+            }
+            format!("{}@{}", number, count - 1)
+        } else {
+            self.forward.push((number.to_string(), count, line));
+            format!("{}@{}", number, count)
+        }
+    }
+
     pub fn read_line_num(self: &Lexer, file_data: &String, line: usize) -> String {
         let mut index: usize = 0;
         let mut filedescriptor: usize = 0;
@@ -89,236 +570,432 @@ impl Lexer {
         return line_string;
     }
 
-    fn manage_includes(self: &Lexer, data_i: String) -> String {
-        let mut changes: usize = 1;
-        let mut data = format!("{}", data_i);
-        let mut new: String = String::new();
-        let mut include: bool = false;
-        let mut modified: bool = false;
+    // `include "file"` is replaced with the contents of the file, once per
+    // file. returns the expanded text along with the file (None for the one
+    // being compiled) and line each of its lines came from.
+    fn manage_includes(self: &Lexer, data_i: String) -> (String, Vec<(Option<String>, usize)>) {
+        let mut lines: Vec<(String, Option<String>, usize)> = data_i.split('\n')
+            .enumerate()
+            .map(|(number, text)| (text.to_string(), None, number))
+            .collect();
         let mut files: Vec<String> = Vec::new();
+        let mut changes: bool = true;
 
-        while changes != 0 {
-            changes = 0;
-            if data.contains("include") {
-                new = String::new();
-                for c in data.replace("\n", "\n ").split(" ") {
-                    if include {
-                        let modified_c = c.replace("\n", "");
-                        let replaced_sm = modified_c.replace("\"", "");
-                        if !modified_c.contains("\"") || modified_c.matches("\"").count() < 2{
+        while changes {
+            changes = false;
+            let mut new: Vec<(String, Option<String>, usize)> = Vec::new();
+            for (text, file, number) in lines {
+                if !text.contains("include") {
+                    new.push((text, file, number));
+                    continue;
+                }
+                let mut rest: Vec<&str> = Vec::new();
+                let mut words = text.split(' ');
+                while let Some(word) = words.next() {
+                    if word == "include" {
+                        changes = true;
+                        let quoted = words.next().unwrap_or("");
+                        if quoted.matches("\"").count() < 2 {
                             format_errora("Filename must be surrounded in a pair of \"\" for include.".to_string());
                             process::exit(1);
                         }
-                        if !files.contains(&format!("{}", modified_c)) {
-                            let contents = fs::read_to_string(replaced_sm.clone()).expect(&*format!("Unable to read file '{}'", replaced_sm));
-                            new += &*contents;
-                            files.push(format!("{}", modified_c));
+                        // whatever came before the include keeps its line
+                        if !rest.is_empty() {
+                            new.push((rest.join(" "), file.clone(), number));
+                            rest.clear();
                         }
-                        include = !include;
-                        continue;
-                    }
-                    if c == "include" {
-                        include = true;
-                        modified = true;
-                        changes = 1;
-                    } else if c.contains("include") {
+                        if !files.iter().any(|f| f == quoted) {
+                            let filename = quoted.replace("\"", "");
+                            let contents = fs::read_to_string(&filename).expect(&*format!("Unable to read file '{}'", filename));
+                            for (included, line) in contents.split_terminator('\n').zip(0..) {
+                                new.push((included.to_string(), Some(filename.clone()), line));
+                            }
+                            files.push(quoted.to_string());
+                        }
+                    } else if word.contains("include") {
                         format_errora("Include must take the following form: `include \"<filename>\"`.".to_string());
                         process::exit(1);
+                    } else {
+                        rest.push(word);
                     }
-                    else {
-                        new += &*format!("{} ", c);
-                    }
                 }
-                data = new.clone();
+                if !rest.is_empty() {
+                    new.push((rest.join(" "), file, number));
+                }
             }
+            lines = new;
         }
-        if !modified { new = data_i.clone(); }
-        return new;
+        let data = lines.iter().map(|(text, _, _)| text.as_str()).collect::<Vec<&str>>().join("\n");
+        (data, lines.into_iter().map(|(_, file, number)| (file, number)).collect())
     }
 
 
+    // `import "lib.a"` declares everything an object or archive defines: its
+    // methods as `extern`s and its constants with `let`. the declarations go
+    // on the line of the import, so later lines keep their numbers.
+    fn manage_imports(self: &mut Lexer, data: String) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        for line in data.split('\n') {
+            let rest = match line.trim_start().strip_prefix("import") {
+                Some(rest) if rest.starts_with([' ', '\t']) => rest.trim(),
+                _ => {
+                    lines.push(line.to_string());
+                    continue;
+                }
+            };
+            let filename = match rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+                Some(filename) => filename,
+                None => {
+                    format_errora("Import must take the following form: `import \"<filename>\"`.".to_string());
+                    process::exit(1);
+                }
+            };
+            let mut declarations: Vec<String> = Vec::new();
+            for object in Library::load(filename).objects() {
+                for (name, _) in &object.globals {
+                    declarations.push(format!("extern {}", name));
+                }
+                for (name, value) in &object.constants {
+                    declarations.push(format!("let {} const {}", value, name));
+                }
+            }
+            self.imports.push(filename.to_string());
+            lines.push(declarations.join(" "));
+        }
+        lines.join("\n")
+    }
+
     pub fn lex_asm(self: &mut Lexer, file_data: String) {
         let mut default_bitlen: bool = false;
-        let chars = file_data.chars();
         let mut line: usize = 0;
         let mut mul_reg: bool = false;
 
-        for i in chars {
-            if i != '\n' && i != '\t' && i != ' ' { self.toks += &String::from(i); }
-            if i == '\n' && (self.lexer_state == 0) {
-                if &*self.toks != "" {
-                    format_errorl("Syntax error".to_string(),
-                                    line, 
-                                    self.read_line_num(&file_data, line),
-                    );
-                    process::exit(1);
-                }
+        self.line = 0;
+        self.source = preprocess(&file_data, &self.defines);
+        self.file_lines = file_data.lines().map(String::from).collect();
+
+        while line < self.source.len() {
+            let mut text = self.source[line].text.clone();
+            if let Some((number, rest)) = split_numeric_label(&text) {
+                self.define_numeric_label(number);
+                text = rest.to_string();
             }
-            if self.lexer_state == 0 {
-                match &*self.toks {
-                    "mov" => { self.add_token(TokenType::INSTRUCTION, "mov"); self.expected = 9; },
-                    "ldr" => { self.add_token(TokenType::INSTRUCTION, "ldr"); self.expected = 5; },
-                    "indl" => { self.add_token(TokenType::INSTRUCTION, "indl"); self.expected = 9; }, 
-                    "cpy" => { self.add_token(TokenType::INSTRUCTION, "cpy"); self.expected = 1; },
-                    "str" => { self.add_token(TokenType::INSTRUCTION, "str"); self.expected = 5; },
-                    "inds" => { self.add_token(TokenType::INSTRUCTION, "inds"); self.expected = 5; },
-                    "push" => { self.add_token(TokenType::INSTRUCTION, "push"); self.expected = 1; },
-                    "pop" => { self.add_token(TokenType::INSTRUCTION, "pop"); self.expected = 1; },
-                    "iadd" => { self.add_token(TokenType::INSTRUCTION, "iadd"); self.expected = 1; },
-                    "isub" => { self.add_token(TokenType::INSTRUCTION, "isub"); self.expected = 1; },
-                    "imul" => { self.add_token(TokenType::INSTRUCTION, "imul"); self.expected = 1; },
-                    "idiv" => { self.add_token(TokenType::INSTRUCTION, "idiv"); self.expected = 1; },
-                    "dadd" => { self.add_token(TokenType::INSTRUCTION, "dadd"); self.expected = 1; },
-                    "dsub" => { self.add_token(TokenType::INSTRUCTION, "dsub"); self.expected = 1; },
-                    "dmul" => { self.add_token(TokenType::INSTRUCTION, "dmul"); self.expected = 1; },
-                    "ddiv" => { self.add_token(TokenType::INSTRUCTION, "ddiv"); self.expected = 1; },
-                    "icst" => { self.add_token(TokenType::INSTRUCTION, "icst"); self.expected = 1; },
-                    "dcst" => { self.add_token(TokenType::INSTRUCTION, "dcst"); self.expected = 1; },
-                    "shl" => { self.add_token(TokenType::INSTRUCTION, "shl"); self.expected = 1; },
-                    "shr" => { self.add_token(TokenType::INSTRUCTION, "shr"); self.expected = 1; },
-                    "and" => { self.add_token(TokenType::INSTRUCTION, "and"); self.expected = 1; },
-                    "or" => { self.add_token(TokenType::INSTRUCTION, "or"); self.expected = 1; },
-                    "neg" => { self.add_token(TokenType::INSTRUCTION, "neg"); self.expected = 1; },
-                    "icmp" => { self.add_token(TokenType::INSTRUCTION, "icmp"); self.expected = 1; },
-                    "dcmp" => { self.add_token(TokenType::INSTRUCTION, "dcmp"); self.expected = 1; },
-                    "jmp" => { self.add_token(TokenType::INSTRUCTION, "jmp"); self.expected = 4; },
-                    "beq" => { self.add_token(TokenType::INSTRUCTION, "beq"); self.expected = 4; },
-                    "bne" => { self.add_token(TokenType::INSTRUCTION, "bne"); self.expected = 4; },
-                    "bgt" => { self.add_token(TokenType::INSTRUCTION, "bgt"); self.expected = 4; },
-                    "blt" => { self.add_token(TokenType::INSTRUCTION, "blt"); self.expected = 4; },
-                    "jsr" => { self.add_token(TokenType::INSTRUCTION, "jsr"); self.expected = 4; },
-                    "ret" => { self.add_token(TokenType::INSTRUCTION, "ret"); self.expected = 0; },
-                    "sys" => { self.add_token(TokenType::INSTRUCTION, "sys"); self.expected = 4; },
-                    "halt" => { self.add_token(TokenType::INSTRUCTION, "halt"); self.expected = 0; },
-                    "adr" => { self.add_token(TokenType::INSTRUCTION, "adr"); self.expected = 5; },
-                    "lei" => { self.add_token(TokenType::INSTRUCTION, "lei"); self.expected = 1; },
-                    "lst" => { self.add_token(TokenType::INSTRUCTION, "lst"); self.expected = 1; },
-                    "ltt" => { self.add_token(TokenType::INSTRUCTION, "ltt"); self.expected = 1; },
-                    "lsf" => { self.add_token(TokenType::INSTRUCTION, "lsf"); self.expected = 1; },
-                    "sei" => { self.add_token(TokenType::INSTRUCTION, "sei"); self.expected = 1; },
-                    "sst" => { self.add_token(TokenType::INSTRUCTION, "sst"); self.expected = 1; },
-                    "stt" => { self.add_token(TokenType::INSTRUCTION, "stt"); self.expected = 1; },
-                    "ssf" => { self.add_token(TokenType::INSTRUCTION, "ssf"); self.expected = 1; },
-                    "bufc" => { self.add_token(TokenType::INSTRUCTION, "bufc"); self.expected = 1; },
-                    "bseq" => { self.add_token(TokenType::INSTRUCTION, "bseq"); self.expected = 1; },
-                    "lseq" => { self.add_token(TokenType::INSTRUCTION, "lseq"); self.expected = 1; },
-                    "pow" => { self.add_token(TokenType::INSTRUCTION, "pow"); self.expected = 1; },
-                    "root" => { self.add_token(TokenType::INSTRUCTION, "root"); self.expected = 1; },
-                    "call" => { self.add_token(TokenType::INSTRUCTION, "call"); self.expected = 1; },
-                    //"externo" => self.add_token(TokenType::INSTRUCTION, "pop"),
-                    "$" => { default_bitlen = true; self.toks = String::from(""); }
-                    "U$" => { default_bitlen = false; self.toks = String::from(""); }
-                    "0x" => { self.set_int_bit(); self.expr = String::from(""); self.toks = String::from(""); },
-                    "r" => { self.set_prereg_bit(); self.toks = String::from(""); self.expr = String::from(""); }
-                    "." => { self.set_label_bit(); self.toks = String::from(""); self.expr = String::from(""); }
-                    "\"" => { self.set_string_bit(); self.toks = String::from(""); self.expr = String::from(""); }
-                    ";" => { self.set_comment_bit(); }
-                    _ => ()
-                }
-            } else {
-                if (self.lexer_state & 0x02 != 0) && (i != '\n' && i != ' ' && i != '\t') {
-                    self.expr += &String::from(i);
-                } else if self.lexer_state & 0x02 != 0 {
-                    self.add_token(TokenType::INTEGER, &*format!("{}{}", if default_bitlen { "L" } else { "U" }, self.expr));
-                    self.expected -= if default_bitlen { 8 } else { 4 };
-                    self.clear_state();
-                    self.toks = String::from("");
-                    default_bitlen = false;
+            if self.lex_directive(&text, line) || self.lex_expression(&text, line) {
+                line += 1;
+                self.line = line;
+                continue;
+            }
+            for i in text.chars().chain(['\n']) {
+                if i != '\n' && i != '\t' && i != ' ' { self.toks += &String::from(i); }
+                if i == '\n' && (self.lexer_state == 0) {
+                    if &*self.toks != "" {
+                        format_error_origin("Syntax error".to_string(), &self.source[line]);
+                        process::exit(1);
+                    }
                 }
-
-                if (self.lexer_state & 0x08) != 0 {
-                    if self.toks == "e" || self.toks == "o" {
+                if self.lexer_state == 0 {
+                    match &*self.toks {
+                        //"externo" => self.add_token(TokenType::INSTRUCTION, "pop"),
+                        mnemonic if operand_bytes(mnemonic).is_some() => {
+                            self.expected = operand_bytes(mnemonic).unwrap();
+                            self.add_token(TokenType::INSTRUCTION, &self.toks.clone());
+                        },
+                        "$" => { default_bitlen = true; self.toks = String::from(""); }
+                        "U$" => { default_bitlen = false; self.toks = String::from(""); }
+                        "0x" => { self.set_int_bit(); self.expr = String::from(""); self.toks = String::from(""); },
+                        "r" => { self.set_prereg_bit(); self.toks = String::from(""); self.expr = String::from(""); }
+                        "." => { self.set_label_bit(); self.toks = String::from(""); self.expr = String::from(""); }
+                        "\"" => { self.set_string_bit(); self.toks = String::from(""); self.expr = String::from(""); }
+                        ";" => { self.set_comment_bit(); }
+                        _ => ()
+                    }
+                } else {
+                    if (self.lexer_state & 0x02 != 0) && (i != '\n' && i != ' ' && i != '\t') {
+                        self.expr += &String::from(i);
+                    } else if self.lexer_state & 0x02 != 0 {
+                        self.add_token(TokenType::INTEGER, &*format!("{}{}", if default_bitlen { "L" } else { "U" }, self.expr));
+                        self.expected -= if default_bitlen { 8 } else { 4 };
                         self.clear_state();
-                        if self.toks == "e" { self.toks = String::from("re"); }
-                        else { self.toks = String::from("ro"); }
+                        self.toks = String::from("");
+                        default_bitlen = false;
+                    }
+
+                    if (self.lexer_state & 0x08) != 0 {
+                        if self.toks == "e" || self.toks == "o" {
+                            self.clear_state();
+                            if self.toks == "e" { self.toks = String::from("re"); }
+                            else { self.toks = String::from("ro"); }
+                        }
+                        else {
+                            if i != '\n' && i != ',' && i != '\t' && i != ' ' {
+                                self.expr += &String::from(i);
+                            } else {
+                                self.add_token(TokenType::REGISTER, &*self.expr.clone());
+                                self.toks = String::from("");
+                                self.clear_state();
+                                if !mul_reg {
+                                    self.expected -= 1;
+                                    mul_reg = !mul_reg;
+                                } else {
+                                    mul_reg = !mul_reg;
+                                }
+                            }
+                        }
                     }
-                    else {
-                        if i != '\n' && i != ',' && i != '\t' && i != ' ' {
+
+                    if (self.lexer_state & 0x04) != 0 {
+                        if i != ':' && i != ' ' && i != '\t' && i != ',' && i != '\n' {
                             self.expr += &String::from(i);
                         } else {
-                            self.add_token(TokenType::REGISTER, &*self.expr.clone());
+                            if i == ':' {
+                                self.add_token(TokenType::LABEL, &*format!("D{}", self.expr));
+                            }
+                            else {
+                                self.add_token(TokenType::LABEL, &*format!("U{}", self.expr));
+                                self.expected -= 4;
+                            }
                             self.toks = String::from("");
                             self.clear_state();
-                            if !mul_reg {
-                                self.expected -= 1;
-                                mul_reg = !mul_reg;
-                            } else {
-                                mul_reg = !mul_reg;
-                            }
                         }
                     }
-                }
 
-                if (self.lexer_state & 0x04) != 0 {
-                    if i != ':' && i != ' ' && i != '\t' && i != ',' && i != '\n' {
-                        self.expr += &String::from(i);
-                    } else {
-                        if i == ':' {
-                            self.add_token(TokenType::LABEL, &*format!("D{}", self.expr));
+                    if (self.lexer_state & (1 << 4)) != 0 {
+                        if i == '\n' {
+                            self.toks = String::from("");
+                            self.clear_state();
                         }
-                        else {
-                            self.add_token(TokenType::LABEL, &*format!("U{}", self.expr));
-                            self.expected -= 4;
+                    }
+
+                    if (self.lexer_state & 0x01) != 0 {
+                        if i != '"' {
+                            self.expr += &String::from(i);
+                        } else {
+                            self.add_token(TokenType::STRING, &*self.expr.clone());
+                            self.toks = String::from("");
+                            self.clear_state();
                         }
-                        self.toks = String::from("");
-                        self.clear_state();
                     }
                 }
-
-                if (self.lexer_state & (1 << 4)) != 0 {
-                    if i == '\n' {
-                        self.toks = String::from("");
-                        self.clear_state();
+                if i == '\n' {
+                    mul_reg = false;
+                    if self.expected != 0 {
+                        format_error_origin("Invalid operands for instruction".to_string(), &self.source[line]);
+                        println!("{}", self.expected);
+                        process::exit(1);
                     }
+                    if self.lexer_state == 1 {
+                        format_error_origin("Unclosed delimiter (did you forget a \"?)".to_string(), &self.source[line]);
+                        process::exit(1);
+                    }
+                    line += 1;
+                    self.line = line;
                 }
+            }
+        }
 
-                if (self.lexer_state & 0x01) != 0 {
-                    if i != '"' {
-                        self.expr += &String::from(i);
-                    } else {
-                        self.add_token(TokenType::STRING, &*self.expr.clone());
-                        self.toks = String::from("");
-                        self.clear_state();
+        for (number, count, line) in std::mem::take(&mut self.forward) {
+            if self.numeric.get(&number).copied().unwrap_or(0) <= count {
+                self.fail_at(line, format!("`{}f` has no `{}:` after it.", number, number));
+            }
+        }
+    }
+
+    // methods and constants can not take the name of a keyword, as every use
+    // of the name would be read as the keyword instead. `line` is the line
+    // the name is on.
+    fn reject_reserved(self: &Lexer, file_data: &String, line: usize) {
+        if RESERVED_WORDS.contains(&&*self.expr) {
+            format_errorl(format!("`{}` is a reserved word.", self.expr), line + 1, self.read_line_num(file_data, line));
+            process::exit(1);
+        }
+    }
+
+    fn fail_at(self: &Lexer, line: usize, error: String) -> ! {
+        format_error_origin(error, &self.source[line]);
+        process::exit(1);
+    }
+
+    // data directives are handled a whole line at a time, optionally after
+    // a label definition (`.table: .byte 1, 2, 3`). returns false for lines
+    // that hold anything else.
+    fn lex_directive(self: &mut Lexer, text: &str, line: usize) -> bool {
+        let (label, rest) = split_label(text);
+        let (directive, operands) = match rest.split_once(|c: char| c.is_whitespace()) {
+            Some((directive, operands)) => (directive, operands),
+            None => (rest, "")
+        };
+        let width = match directive {
+            ".byte" => 1,
+            ".word" => 2,
+            ".dword" => 4,
+            ".quad" => 8,
+            ".zero" | ".align" | ".ascii" | ".equ" | ".global" | ".memory" | ".incbin" => 0,
+            _ => return false
+        };
+        if let Some(name) = label {
+            self.add_token(TokenType::LABEL, &format!("D{}", name));
+        }
+
+        let operands = split_operands(operands);
+        if operands.is_empty() || operands.iter().any(|o| o.is_empty()) {
+            self.fail_at(line, format!("`{}` is missing an operand.", directive));
+        }
+
+        match directive {
+            ".equ" => {
+                let definition = operands.join(", ");
+                match definition.split_once(|c: char| c.is_whitespace()) {
+                    Some((name, value)) if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+                        && !name.starts_with(|c: char| c.is_ascii_digit()) => {
+                        self.add_token(TokenType::DIRECTIVE, &format!("equ {} {}:{}", name, line, value.trim()));
+                    },
+                    _ => self.fail_at(line, "`.equ` expects a name and a value. (eg. `.equ SIZE 8 * 4`)".to_string())
+                }
+            },
+            ".incbin" => {
+                let filename = match operands[0].strip_prefix('"').and_then(|o| o.strip_suffix('"')) {
+                    Some(filename) if operands.len() == 1 && !filename.is_empty() => filename,
+                    _ => self.fail_at(line, "`.incbin` expects a filename in quotes. (eg. `.incbin \"data.bin\"`)".to_string())
+                };
+                let data = match fs::read(filename) {
+                    Ok(data) => data,
+                    Err(why) => self.fail_at(line, format!("Couldn't read `{}`: {}", filename, why))
+                };
+                for byte in data {
+                    self.add_token(TokenType::INTEGER, &format!("B{:x}", byte));
+                }
+            },
+            ".global" => {
+                for operand in &operands {
+                    let name = operand.strip_prefix('.').unwrap_or(operand);
+                    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"') || name.contains("..") {
+                        self.fail_at(line, format!("`.global` expects label names, found `{}`.", operand));
                     }
+                    self.add_token(TokenType::DIRECTIVE, &format!("global {}", name));
                 }
-            }
-            if i == '\n' {
-                mul_reg = false;
-                if self.expected != 0 {
-                    format_errorl("Invalid operands for instruction".to_string(),
-                                    line, 
-                                    self.read_line_num(&file_data, line),
-                    );
-                    println!("{}", self.expected);
-                    process::exit(1);
+            },
+            ".zero" | ".align" | ".memory" => {
+                let value = match parse_count(directive, &operands) {
+                    Ok(value) => value,
+                    Err(error) => self.fail_at(line, error)
+                };
+                self.add_token(TokenType::DIRECTIVE, &format!("{} {}", &directive[1..], value));
+            },
+            ".ascii" => {
+                for operand in &operands {
+                    match operand.strip_prefix('"').and_then(|o| o.strip_suffix('"')) {
+                        Some(string) if operand.len() >= 2 => {
+                            self.add_token(TokenType::DIRECTIVE, "ascii");
+                            self.add_token(TokenType::STRING, string);
+                        },
+                        _ => self.fail_at(line, format!("`.ascii` expects quoted strings, found `{}`.", operand))
+                    }
                 }
-                if self.lexer_state == 1 {
-                    format_errorl("Unclosed delimiter (did you forget a \"?)".to_string(),
-                                    line, 
-                                    self.read_line_num(&file_data, line),
-                    );
-                    process::exit(1);
+            },
+            _ => {
+                for operand in &operands {
+                    // addresses are 4 bytes wide, so labels fit in a .dword
+                    // or the low half of a .quad
+                    if is_numeric_reference(operand) {
+                        let name = self.numeric_reference(operand, line);
+                        self.add_token(TokenType::LABEL, &format!("E{}:{}:.{}", width, line, name));
+                        continue;
+                    }
+                    if let Some(name) = operand.strip_prefix('.').filter(|_| width >= 4 && is_plain_label(operand)) {
+                        self.add_token(TokenType::LABEL, &format!("U{}", name));
+                        if width == 8 { self.add_token(TokenType::INTEGER, "U0"); }
+                        continue;
+                    }
+                    let value = match parse_literal(operand) {
+                        Some(value) => value,
+                        None if width == 8 && parse_float(operand).is_some() => to_u64(parse_float(operand).unwrap()),
+                        None => {
+                            self.add_token(TokenType::LABEL, &format!("E{}:{}:{}", width, line, operand));
+                            continue;
+                        }
+                    };
+                    if !fits_width(value, width) {
+                        self.fail_at(line, format!("`{}` does not fit in {} byte(s).", operand, width));
+                    }
+                    let masked = if width == 8 { value } else { value & ((1 << (width * 8)) - 1) };
+                    self.add_token(TokenType::INTEGER, &format!("{}{:x}", width_prefix(width), masked));
                 }
-                line += 1;
             }
         }
+        true
     }
 
-    pub fn assemble_asm(self: &mut Lexer) -> usize {
-        let path = Path::new("out.bin");
-        let mut file = match File::create(&path) {
-            Err(why) => panic!("Couldn't create file {}: {}", "out.bin", why),
-            Ok(file) => file
+    // instructions taking an immediate are lexed a whole line at a time, like
+    // directives, so that the width comes from the instruction. the value is
+    // left for `assemble_asm` to evaluate once labels have addresses.
+    fn lex_expression(self: &mut Lexer, text: &str, line: usize) -> bool {
+        let (label, rest) = split_label(text);
+        let (mnemonic, operands) = match rest.split_once(|c: char| c.is_whitespace()) {
+            Some((mnemonic, operands)) => (mnemonic, operands),
+            None => return false
+        };
+        let width = match immediate_bytes(mnemonic) {
+            Some(width) => width,
+            None => return false
+        };
+        let operands = split_operands(operands);
+        let registers = operands.iter().filter(|o| is_register(o)).count();
+        let immediates: Vec<&str> = operands.iter()
+            .filter(|o| !is_register(o))
+            .map(|o| o.strip_prefix("U$").or(o.strip_prefix('$')).unwrap_or(o))
+            .collect();
+        // the lexer reads plain labels as 4 byte addresses by itself
+        if immediates.len() == 1 && is_plain_label(immediates[0]) && width == 4 {
+            return false;
+        }
+        let wanted_registers = if operand_bytes(mnemonic) == Some(4) { 0 } else { 1 };
+        if immediates.len() != 1 || immediates[0].is_empty() || registers != wanted_registers {
+            self.fail_at(line, "Invalid operands for instruction".to_string());
+        }
+
+        if let Some(name) = label {
+            self.add_token(TokenType::LABEL, &format!("D{}", name));
+        }
+        self.add_token(TokenType::INSTRUCTION, mnemonic);
+        for operand in operands.iter().filter(|o| is_register(o)) {
+            self.add_token(TokenType::REGISTER, &operand[1..]);
+        }
+        let immediate = if is_numeric_reference(immediates[0]) {
+            format!(".{}", self.numeric_reference(immediates[0], line))
+        } else {
+            immediates[0].to_string()
         };
+        match parse_float(&immediate) {
+            Some(value) if width == 8 => self.add_token(TokenType::INTEGER, &format!("L{:x}", to_u64(value))),
+            Some(_) => self.fail_at(line, format!("`{}` is a double, which only fits a 64-bit operand.", immediate)),
+            None => self.add_token(TokenType::LABEL, &format!("E{}:{}:{}", width, line, immediate))
+        }
+        true
+    }
+
+    pub fn assemble_asm(self: &mut Lexer) -> usize {
         let mut file_vec: Vec<String> = Vec::new();
         let mut output_vec: Vec<u8> = Vec::new();
         let mut label_table: HashMap::<String, usize> = HashMap::new();
+        let mut line_marks: Vec<(usize, usize)> = Vec::new();
+        // where the code for each token starts, for the listing
+        let mut token_marks: Vec<(usize, usize)> = Vec::new();
+        let mut constants: HashMap::<String, String> = self.defines.iter().cloned().collect();
+        // `.global` names with the line they were given on
+        let mut globals: Vec<(String, usize)> = Vec::new();
+        let mut memory: usize = 0;
+        // the strictest `.align`, which an object has to be placed at
+        let mut align: usize = 1;
 
         let mut token_ind: usize;
 
         token_ind = 0;
 
         while token_ind < self.tokens.len() {
+            token_marks.push((file_vec.len(), self.tokens[token_ind].line));
+            if self.tokens[token_ind].token_t == TokenType::INSTRUCTION {
+                line_marks.push((file_vec.len(), self.tokens[token_ind].line));
+            }
             match &self.tokens[token_ind].token_t {
                 TokenType::INSTRUCTION => match &*self.tokens[token_ind].data {
                     "mov" => file_vec.push(String::from("00")),
@@ -370,6 +1047,15 @@ impl Lexer {
                     "pow" => file_vec.push(String::from("2f")),
                     "root" => file_vec.push(String::from("30")),
                     "call" => file_vec.push(String::from("31")),
+                    "ald" => file_vec.push(String::from("32")),
+                    "ast" => file_vec.push(String::from("33")),
+                    "cas" => file_vec.push(String::from("34")),
+                    "xadd" => file_vec.push(String::from("35")),
+                    "try" => { file_vec.push(String::from("36")); file_vec.push(String::from("00")); }
+                    "endtry" => { file_vec.push(String::from("37")); file_vec.push(String::from("00")); }
+                    "throw" => file_vec.push(String::from("38")),
+                    "int" => file_vec.push(String::from("39")),
+                    "iret" => { file_vec.push(String::from("3a")); file_vec.push(String::from("00")); }
                     _ => println!("Unimplemented instruction!")
                 },
                 TokenType::REGISTER => {
@@ -388,52 +1074,53 @@ impl Lexer {
                     let mut chars = self.tokens[token_ind].data.chars();
                     chars.next();
                     let string = chars.as_str();
-                    if self.tokens[token_ind].data.chars().next().unwrap() == 'L' {
-                        let mut value: u64 = u64::from_str_radix(string, 16).unwrap();
-                        for _ in 0..8 {
-                            file_vec.push(format!("{:x}", value & 0xFF));
-                            value = value >> 8;
-                        }
-                    } else {
-                        let mut value: u32 = u32::from_str_radix(string, 16).unwrap();
-                        for _ in 0..4 {
-                            file_vec.push(format!("{:x}", value & 0xFF));
-                            value = value >> 8;
-                        }
+                    let width = match self.tokens[token_ind].data.chars().next().unwrap() {
+                        'L' => 8,
+                        'W' => 2,
+                        'B' => 1,
+                        _ => 4
+                    };
+                    let mut value: u64 = u64::from_str_radix(string, 16).unwrap();
+                    for _ in 0..width {
+                        file_vec.push(format!("{:x}", value & 0xFF));
+                        value = value >> 8;
                     }
                 },
                 TokenType::LABEL => {                    
                     file_vec.push(format!("{}", self.tokens[token_ind].data));
                 }
                 TokenType::STRING => {
-                    let mut escape = false;
-                    for chars in self.tokens[token_ind].data.chars() {
-                        if chars == '\\' {
-                            escape = true;
-                        }
-                        else if !escape { file_vec.push(format!("{:x}", chars as u8)); }
-                        else {
-                            match chars {
-                                'n' => file_vec.push("0a".to_string()),
-                                't' => file_vec.push("09".to_string()),
-                                'v' => file_vec.push("0b".to_string()),
-                                '"' => file_vec.push("22".to_string()),
-                                'r' => file_vec.push("0d".to_string()),
-                                'f' => file_vec.push("0c".to_string()),
-                                'b' => file_vec.push("08".to_string()),
-                                'a' => file_vec.push("07".to_string()),
-                                'e' => file_vec.push("1b".to_string()),
-                                '\'' => file_vec.push("27".to_string()),
-                                '\\' => file_vec.push("5c".to_string()),
-                                _ => {
-                                    format_errora(format!("Unknown escape sequence found! ('\\{}')", chars));
-                                    process::exit(1);
-                                }
+                    push_string(&mut file_vec, &self.tokens[token_ind].data);
+                    file_vec.push(String::from("00"));
+                },
+                TokenType::DIRECTIVE => {
+                    let directive = self.tokens[token_ind].data.clone();
+                    match directive.split_once(' ') {
+                        Some(("equ", definition)) => {
+                            let (name, value) = definition.split_once(' ').unwrap();
+                            let (line, value) = value.split_once(':').unwrap();
+                            if constants.insert(name.to_string(), value.to_string()).is_some() {
+                                self.fail_at(line.parse().unwrap(), format!("Multiple definitions of `{}`.", name));
                             }
-                            escape = false;
+                        },
+                        Some(("global", name)) => globals.push((name.to_string(), self.tokens[token_ind].line)),
+                        Some(("memory", size)) => memory += size.parse::<usize>().unwrap(),
+                        Some(("zero", count)) => {
+                            for _ in 0..count.parse::<usize>().unwrap() {
+                                file_vec.push(String::from("00"));
+                            }
+                        },
+                        // padding is worked out once addresses are known
+                        Some(("align", boundary)) => {
+                            align = align.max(boundary.parse().unwrap());
+                            file_vec.push(format!("A{}", boundary));
+                        },
+                        _ => {
+                            // `.ascii`: the string that follows, unterminated
+                            token_ind += 1;
+                            push_string(&mut file_vec, &self.tokens[token_ind].data);
                         }
                     }
-                    file_vec.push(String::from("00"));
                 },
                 _ => warninga("Unimplemented token found!"),
             }
@@ -443,8 +1130,11 @@ impl Lexer {
         // to just use something I like to call a "post-processor".
         
         let mut passed: usize = 0;
+        let mut relocations: Vec<Relocation> = Vec::new();
+        let mut index_addr: Vec<usize> = Vec::with_capacity(file_vec.len());
 
         for label in &file_vec {
+            index_addr.push(passed);
             let mut chars = label.chars();
             chars.next();
             let reduced = chars.as_str();
@@ -452,13 +1142,50 @@ impl Lexer {
                 label_table.insert(reduced.to_string(), passed);
             } else if label.chars().next().unwrap() == 'U' {
                 passed += 4;
+            } else if label.chars().next().unwrap() == 'A' {
+                let boundary: usize = reduced.parse().unwrap();
+                passed = passed.div_ceil(boundary) * boundary;
+            } else if label.starts_with('E') {
+                passed += reduced.split(':').next().unwrap().parse::<usize>().unwrap();
             } else {
                 passed += 1;
             }
         }
 
         for passed_bytes in &file_vec {
-            if passed_bytes.chars().next().unwrap() != 'D' && passed_bytes.chars().next().unwrap() != 'U' {
+            if let Some(boundary) = passed_bytes.strip_prefix('A') {
+                let boundary: usize = boundary.parse().unwrap();
+                while !output_vec.len().is_multiple_of(boundary) {
+                    output_vec.push(0);
+                }
+            } else if let Some(expression) = passed_bytes.strip_prefix('E') {
+                // E<width>:<line>:<expression>
+                let mut fields = expression.splitn(3, ':');
+                let width: usize = fields.next().unwrap().parse().unwrap();
+                let line: usize = fields.next().unwrap().parse().unwrap();
+                let expression = fields.next().unwrap();
+                let evaluated = if self.relocatable() {
+                    relocate_operand(expression, &label_table, &constants)
+                } else {
+                    evaluate_operand(expression, &label_table, &constants, FLAT, &RefCell::new(Vec::new()), &mut Vec::new())
+                        .map(|value| (value, None))
+                };
+                let value = match evaluated {
+                    Ok((value, None)) => value,
+                    Ok((value, Some(target))) => {
+                        if width < 4 {
+                            self.fail_at(line, format!("`{}` is an address, which needs 4 or 8 bytes in an object.", expression));
+                        }
+                        relocations.push(Relocation { offset: output_vec.len(), width, target });
+                        value
+                    },
+                    Err(error) => self.fail_at(line, error)
+                };
+                if !fits_width(value, width) {
+                    self.fail_at(line, format!("`{}` ({:#x}) does not fit in {} byte(s).", expression, value, width));
+                }
+                output_vec.extend_from_slice(&value.to_le_bytes()[..width]);
+            } else if passed_bytes.chars().next().unwrap() != 'D' && passed_bytes.chars().next().unwrap() != 'U' {
                 output_vec.push(u8::from_str_radix(passed_bytes, 16).unwrap());
             } else {
                 let mut chars = passed_bytes.chars();
@@ -467,10 +1194,17 @@ impl Lexer {
                 if passed_bytes.chars().next().unwrap() == 'U' {
                     if label_table.contains_key(&reduced.to_string()) {
                         let mut val: usize = *label_table.get(&reduced.to_string()).unwrap();
+                        if self.relocatable() {
+                            relocations.push(Relocation { offset: output_vec.len(), width: 4, target: Target::Code });
+                        }
                         for _ in 0..4 {
                             output_vec.push((val & 0xff) as u8);
                             val = val >> 8;
                         }
+                    } else if self.relocatable() {
+                        // left for the linker to find in another object
+                        relocations.push(Relocation { offset: output_vec.len(), width: 4, target: Target::Symbol(reduced.to_string()) });
+                        output_vec.extend_from_slice(&[0; 4]);
                     } else {
                         format_errora(format!("Label '{}' does not exist.", reduced));
                         process::exit(1);
@@ -478,27 +1212,137 @@ impl Lexer {
                 }
             }
         }
-        match file.write_all(&output_vec) {
-            Err(why) => panic!("Couldn't write to file {}: {}", "out.bin", why),
-            Ok(_) => ()
+
+        // symbol and line information for runtime diagnostics
+        let mut debug_info = DebugInfo::new();
+        for (name, addr) in &label_table {
+            debug_info.labels.push((*addr, name.clone()));
+        }
+        for (index, line) in line_marks {
+            let (line, text) = match self.source.get(line) {
+                // compiled code points back at the VML it came from, and the
+                // code the compiler adds itself at line 0
+                Some(origin) if !self.vml_source.is_empty() => {
+                    match self.vml_lines.get(origin.file_line()).copied().flatten() {
+                        Some(vml) => self.vml_source[vml].clone(),
+                        None => (0, String::new())
+                    }
+                },
+                Some(origin) => (origin.file_line() + 1, origin.text.clone()),
+                None => (line + 1, String::new())
+            };
+            debug_info.lines.push((index_addr.get(index).copied().unwrap_or(passed), line, text));
+        }
+        debug_info.labels.sort();
+
+        let mut size: usize = output_vec.len();
+        // the map shows where labels end up in the file that is written
+        let mut map_labels: Vec<(usize, String)> = label_table.iter().map(|(name, addr)| (*addr, name.clone())).collect();
+        if self.relocatable() {
+            let mut object = Object::new(&self.output);
+            for (name, line) in &globals {
+                if let Some(addr) = label_table.get(name) {
+                    object.globals.push((name.clone(), *addr));
+                    continue;
+                }
+                if !constants.contains_key(name) {
+                    self.fail_at(*line, format!("`.global` names `{}`, which is not defined in this file.", name));
+                }
+                // constants go along for files that `import` the object
+                match relocate_operand(name, &label_table, &constants) {
+                    Ok((value, None)) => object.constants.push((name.clone(), value)),
+                    Ok(_) => self.fail_at(*line, format!("`{}` holds an address, so it cannot be made `.global`.", name)),
+                    Err(error) => self.fail_at(*line, error)
+                }
+            }
+            object.code = output_vec.clone();
+            object.memory = memory;
+            object.align = align;
+            object.relocations = relocations;
+            object.debug = debug_info;
+            if self.object {
+                if let Err(why) = fs::write(&self.output, object.to_text()) {
+                    panic!("Couldn't write to file {}: {}", self.output, why);
+                }
+            } else {
+                // a program importing libraries is linked with them right away
+                let mut libraries: Vec<Library> = vec![Library::Object(object)];
+                libraries.extend(self.imports.iter().map(|filename| Library::load(filename)));
+                let (binary, debug_info) = link(&select(&libraries));
+                write_binary(&self.output, &binary, &debug_info);
+                size = binary.len();
+                map_labels = debug_info.labels;
+            }
+        } else {
+            write_binary(&self.output, &output_vec, &debug_info);
+        }
+
+        if let Some(filename) = &self.listing {
+            let address = |index: usize| index_addr.get(index).copied().unwrap_or(passed);
+            let mut ranges: Vec<Option<(usize, usize)>> = vec![None; self.source.len()];
+            for (mark, (index, line)) in token_marks.iter().enumerate() {
+                let end = token_marks[mark + 1..].iter()
+                    .find(|(_, next)| next != line)
+                    .map_or(file_vec.len(), |(next, _)| *next);
+                if ranges[*line].is_none() {
+                    ranges[*line] = Some((address(*index), address(end)));
+                }
+            }
+            if let Err(why) = write_listing(filename, &self.file_lines, &self.source, &ranges, &output_vec) {
+                warninga(&format!("couldn't write the listing to {}: {}", filename, why));
+            }
+        }
+        if let Some(filename) = &self.map {
+            let mut values: Vec<(String, String)> = constants.iter()
+                .map(|(name, definition)| {
+                    let value = match evaluate_operand(definition, &label_table, &constants, FLAT,
+                                                       &RefCell::new(Vec::new()), &mut Vec::new()) {
+                        Ok(value) => format!("{:#x}", value),
+                        Err(_) => definition.clone()
+                    };
+                    (name.clone(), value)
+                })
+                .collect();
+            values.sort();
+            if let Err(why) = write_map(filename, &map_labels, &values, size) {
+                warninga(&format!("couldn't write the map to {}: {}", filename, why));
+            }
         }
-        return output_vec.len();
+
+        return size;
     }
 
     pub fn lex_vml(self: &mut Lexer, file_data_pre: String) {
         let mut pass: bool = false;
         let mut variables: Vec<String> = Vec::new();
         let mut methods: Vec<String> = Vec::new();
+        // methods defined in another object, declared with `extern`
+        let mut externs: Vec<String> = Vec::new();
+        let mut extern_decl: bool = false;
         let mut line: usize = 0;
         let mut braces: i32 = 0;
 
         // manage includes
-        let file_data: String = self.manage_includes(file_data_pre);
+        let (file_data, origins) = self.manage_includes(file_data_pre);
+        let file_data: String = self.manage_imports(file_data);
+        // imports keep the number of lines, so the origins still line up
+        self.vml_source = file_data.split('\n').zip(origins).map(|(text, (file, number))| match file {
+            Some(file) => (number + 1, format!("({}) {}", file, text.trim())),
+            None => (number + 1, text.trim().to_string())
+        }).collect();
 
-        for i in file_data.chars() {
+        let is_word = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+        let mut chars = file_data.chars().peekable();
+        while let Some(i) = chars.next() {
+            self.line = line;
+            // a keyword is only matched once its whole word has been read, so
+            // `freeze` and `thrower` are names rather than `free` and `throw`
+            let word_ends = !is_word(i) || !chars.peek().is_some_and(|c| is_word(*c));
             if i != '\n' && i != '\t' && i != ' ' { self.toks += &*format!("{}", i); }
             if i == '\n' {
-                if self.toks != "".to_string() && self.lexer_state == 0 && !variables.contains(&self.toks) && !methods.contains(&self.toks) {
+                let method_ref = self.toks.strip_prefix('$').unwrap_or(&self.toks).to_string();
+                if self.toks != "".to_string() && self.lexer_state == 0 && !variables.contains(&self.toks)
+                    && !methods.contains(&method_ref) && !externs.contains(&method_ref) {
                     format_errorl("Syntax error".to_string(), line, self.read_line_num(&file_data, line));
                     process::exit(1);
                 }
@@ -523,6 +1367,7 @@ impl Lexer {
                     if i != ' ' && i != '\t' && i != '\n' {
                         self.expr += &*format!("{}", i);
                     } else {
+                        self.reject_reserved(&file_data, if i == '\n' { line - 1 } else { line });
                         self.clear_state();
                         self.add_token(TokenType::VARIABLE_DECL, &*format!("{}", self.expr));
                         variables.push(format!("{}", self.expr));
@@ -542,6 +1387,19 @@ impl Lexer {
                 if !pass {
                     if i != ' ' && i != '\t' && i != '\n' {
                         self.expr += &*format!("{}", i);
+                    } else if extern_decl {
+                        if self.expr.is_empty() || self.expr == "{" {
+                            format_errorl("`extern` without name. (`extern`s should be declared with `extern` <method>)".to_string(),
+                                line,
+                                self.read_line_num(&file_data, line)
+                            );
+                            process::exit(1);
+                        }
+                        self.reject_reserved(&file_data, if i == '\n' { line - 1 } else { line });
+                        externs.push(self.expr.clone());
+                        extern_decl = false;
+                        self.clear_state();
+                        self.toks = String::from("");
                     } else {
                         if methods.contains(&self.expr) {
                             format_errorl(format!("Repeated definition of method `{}`.", self.expr),
@@ -563,6 +1421,7 @@ impl Lexer {
                             );
                             process::exit(1);
                         }
+                        self.reject_reserved(&file_data, if i == '\n' { line - 1 } else { line });
                         methods.push(format!("{}", self.expr));
                         self.clear_state();
                         self.add_token(TokenType::METHOD, &*format!("{}", self.expr));
@@ -602,7 +1461,7 @@ impl Lexer {
                 }
             }
 
-            if self.lexer_state == 0 {
+            if self.lexer_state == 0 && (word_ends || !self.toks.starts_with(char::is_alphabetic)) {
                 match &*self.toks {
                     ">" => self.add_token(TokenType::INSTRUCTION, ">"),
                     "<" => self.add_token(TokenType::INSTRUCTION, "<"),
@@ -652,6 +1511,9 @@ impl Lexer {
                     "method" => {
                         self.set_method_bit(); self.expr = String::from(""); pass = true;
                     }
+                    "extern" => {
+                        self.set_method_bit(); self.expr = String::from(""); pass = true; extern_decl = true;
+                    }
                     "!32" => self.add_token(TokenType::INSTRUCTION, "store32"),
                     "!64" => self.add_token(TokenType::INSTRUCTION, "store64"),
                     "!16" => self.add_token(TokenType::INSTRUCTION, "store16"),
@@ -660,8 +1522,24 @@ impl Lexer {
                     "@64" => self.add_token(TokenType::INSTRUCTION, "load64"),
                     "@16" => self.add_token(TokenType::INSTRUCTION, "load16"),
                     "@8" => self.add_token(TokenType::INSTRUCTION, "load8"),
+                    "!atomic" => self.add_token(TokenType::INSTRUCTION, "atomic_store"),
+                    "@atomic" => self.add_token(TokenType::INSTRUCTION, "atomic_load"),
+                    "atomic-cas" => self.add_token(TokenType::INSTRUCTION, "atomic_cas"),
+                    "atomic-add" => self.add_token(TokenType::INSTRUCTION, "atomic_add"),
                     //"input" => self.add_token(TokenType::INSTRUCTION, "input"),
                     "copy" => self.add_token(TokenType::INSTRUCTION, "copy"),
+                    "alloc" => self.add_token(TokenType::INSTRUCTION, "alloc"),
+                    "free" => self.add_token(TokenType::INSTRUCTION, "free"),
+                    "spawn" => self.add_token(TokenType::INSTRUCTION, "spawn"),
+                    "yield" => self.add_token(TokenType::INSTRUCTION, "yield"),
+                    "join" => self.add_token(TokenType::INSTRUCTION, "join"),
+                    "thread" => self.add_token(TokenType::INSTRUCTION, "thread"),
+                    "try" => self.add_token(TokenType::INSTRUCTION, "try"),
+                    "catch" => self.add_token(TokenType::INSTRUCTION, "catch"),
+                    "throw" => self.add_token(TokenType::INSTRUCTION, "throw"),
+                    "vector" => self.add_token(TokenType::INSTRUCTION, "vector"),
+                    "interrupt" => self.add_token(TokenType::INSTRUCTION, "interrupt"),
+                    "iret" => self.add_token(TokenType::INSTRUCTION, "iret"),
                     "'" => { self.lexer_state = 512; self.expr = String::from(""); },
                     _ => {
                         if i == ' ' || i == '\n' {
@@ -669,10 +1547,17 @@ impl Lexer {
                                 self.add_token(TokenType::VARIABLE, &*self.toks.clone());
                                 self.toks = String::from("");
                             }
-                            if methods.contains(&self.toks) {
+                            if methods.contains(&self.toks) || externs.contains(&self.toks) {
                                 self.add_token(TokenType::LABEL, &*self.toks.clone());
                                 self.toks = String::from("");
                             }
+                            // `$name` is an explicit reference to a method
+                            if self.toks.starts_with('$')
+                                && (methods.contains(&self.toks[1..].to_string()) || externs.contains(&self.toks[1..].to_string())) {
+                                let name = self.toks[1..].to_string();
+                                self.add_token(TokenType::LABEL, &name);
+                                self.toks = String::from("");
+                            }
                         }
                     }
                 }
@@ -757,7 +1642,8 @@ impl Lexer {
                 }
             }
         }
-        if !methods.contains(&("main".to_string())) {
+        // objects are linked with the one holding `main`
+        if !self.object && !methods.contains(&("main".to_string())) {
             format_errora("File does not contain `main` method. Exiting.".to_string());
             process::exit(1);
         }
@@ -768,7 +1654,24 @@ impl Lexer {
     }
 
     pub fn tokens_to_assembly(self: &mut Lexer) -> String {
-        let mut output: String = String::from("; generated by VML compiler v0.0.0a\n\n.start:\n\t\tjmp \t.end\n");
+        let mut output: String = String::from("; generated by VML compiler v0.0.0a\n\n");
+        // pushes 1 if `branch` is taken after comparing the top two values, 0 otherwise
+        output += ".macro compare cmp, branch\n";
+        output += "\t\tpop \tr0\n";
+        output += "\t\tpop \tr1\n";
+        output += "\t\t\\cmp\tr0, r1\n";
+        output += "\t\t\\branch\t.true\n";
+        output += "\t\tmov \tr0, $0x00\n";
+        output += "\t\tjmp \t.join\n";
+        output += ".true:\n";
+        output += "\t\tmov \tr0, $0x01\n";
+        output += ".join:\n";
+        output += "\t\tpush\tr0\n";
+        output += ".endm\n\n";
+        // objects get their start-up code from the linker
+        if !self.relocatable() {
+            output += ".start:\n\t\tjmp \t.end\n";
+        }
         let mut index: usize = 0;
         let mut stringmap: HashMap::<String, String> = HashMap::new();
         let mut loopmap_str: Vec<String> = Vec::new();
@@ -777,10 +1680,16 @@ impl Lexer {
         let mut labels: Vec<String> = Vec::new();
         let mut stringindex: usize = 0;
         let mut loopindex: usize = 0;
-        let mut labelindex: usize = 0;
         let mut memalloc: usize = 0;
+        // the VML line of the token being compiled, and how much of the
+        // output has been attributed to a line so far
+        let mut origin: Option<usize> = None;
+        let mut marked: usize = 0;
 
         while index < self.tokens.len() {
+            self.vml_lines.extend(vec![origin; output[marked..].matches('\n').count()]);
+            marked = output.len();
+            origin = Some(self.tokens[index].line);
             match &self.tokens[index].token_t {
                 TokenType::INTEGER => {
                     let result = self.tokens[index].data.parse::<u64>();
@@ -892,95 +1801,15 @@ impl Lexer {
                             output += "\t\tpush\tr0\n";
                             output += "\t\tpush\tr1\n";
                         },
-                        ">" => {
-                            output += "\t\tpop \tr0\n";
-                            output += "\t\tpop \tr1\n";
-                            output += "\t\ticmp\tr0, r1\n";
-                            output += &*format!("\t\tblt \t.label{}\n", labelindex);
-                            output += "\t\tmov \tr0, $0x00\n";
-                            output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                            output += &*format!(".label{}:\n", labelindex);
-                            output += "\t\tmov \tr0, $0x01\n";
-                            output += &*format!(".label_join{}:\n", labelindex);
-                            output += "\t\tpush\tr0\n";
-                            labelindex += 1;
-                        },
-                        "<" => {
-                            output += "\t\tpop \tr0\n";
-                            output += "\t\tpop \tr1\n";
-                            output += "\t\ticmp\tr0, r1\n";
-                            output += &*format!("\t\tbgt \t.label{}\n", labelindex);
-                            output += "\t\tmov \tr0, $0x00\n";
-                            output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                            output += &*format!(".label{}:\n", labelindex);
-                            output += "\t\tmov \tr0, $0x01\n";
-                            output += &*format!(".label_join{}:\n", labelindex);
-                            output += "\t\tpush\tr0\n";
-                            labelindex += 1;
-                        },
-                        "d>" => {
-                            output += "\t\tpop \tr0\n";
-                            output += "\t\tpop \tr1\n";
-                            output += "\t\tdcmp\tr0, r1\n";
-                            output += &*format!("\t\tblt \t.label{}\n", labelindex);
-                            output += "\t\tmov \tr0, $0x00\n";
-                            output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                            output += &*format!(".label{}:\n", labelindex);
-                            output += "\t\tmov \tr0, $0x01\n";
-                            output += &*format!(".label_join{}:\n", labelindex);
-                            output += "\t\tpush\tr0\n";
-                            labelindex += 1;
-                        },
-                        "d<" => {
-                            output += "\t\tpop \tr0\n";
-                            output += "\t\tpop \tr1\n";
-                            output += "\t\tdcmp\tr0, r1\n";
-                            output += &*format!("\t\tbgt \t.label{}\n", labelindex);
-                            output += "\t\tmov \tr0, $0x00\n";
-                            output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                            output += &*format!(".label{}:\n", labelindex);
-                            output += "\t\tmov \tr0, $0x01\n";
-                            output += &*format!(".label_join{}:\n", labelindex);
-                            output += "\t\tpush\tr0\n";
-                            labelindex += 1;
-                        },
-                        "strequals" => {
-                            output += "\t\tpop \tr0\n";
-                            output += "\t\tpop \tr1\n";
-                            output += "\t\tlseq\tr0, r1\n"; 
-                            output += &*format!("\t\tbeq \t.label{}\n", labelindex);
-                            output += "\t\tmov \tr0, $0x00\n";
-                            output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                            output += &*format!(".label{}:\n", labelindex);
-                            output += "\t\tmov \tr0, $0x01\n";
-                            output += &*format!(".label_join{}:\n", labelindex);
-                            output += "\t\tpush\tr0\n";
-                        },
-                        "strnequals" => {
-                            output += "\t\tpop \tr0\n";
-                            output += "\t\tpop \tr1\n";
-                            output += "\t\tlseq\tr0, r1\n"; 
-                            output += &*format!("\t\tbne \t.label{}\n", labelindex);
-                            output += "\t\tmov \tr0, $0x00\n";
-                            output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                            output += &*format!(".label{}:\n", labelindex);
-                            output += "\t\tmov \tr0, $0x01\n";
-                            output += &*format!(".label_join{}:\n", labelindex);
-                            output += "\t\tpush\tr0\n";
-                        },
+                        ">" => output += "\t\tcompare\ticmp, blt\n",
+                        "<" => output += "\t\tcompare\ticmp, bgt\n",
+                        "d>" => output += "\t\tcompare\tdcmp, blt\n",
+                        "d<" => output += "\t\tcompare\tdcmp, bgt\n",
+                        "strequals" => output += "\t\tcompare\tlseq, beq\n",
+                        "strnequals" => output += "\t\tcompare\tlseq, bne\n",
                         "equals" => {
                             if index >= 2 {
-                                output += "\t\tpop \tr0\n";
-                                output += "\t\tpop \tr1\n";
-                                output += "\t\ticmp\tr0, r1\n"; 
-                                output += &*format!("\t\tbeq \t.label{}\n", labelindex);
-                                output += "\t\tmov \tr0, $0x00\n";
-                                output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                                output += &*format!(".label{}:\n", labelindex);
-                                output += "\t\tmov \tr0, $0x01\n";
-                                output += &*format!(".label_join{}:\n", labelindex);
-                                output += "\t\tpush\tr0\n";
-                                labelindex += 1;
+                                output += "\t\tcompare\ticmp, beq\n";
                             } else {
                                 format_errora("`=` expects two prior arguments. (eg. `1 1 =`).".to_string());
                                 process::exit(1);
@@ -988,37 +1817,16 @@ impl Lexer {
                         }
                         "notequals" => {
                             if index >= 2 {
-                                output += "\t\tpop \tr0\n";
-                                output += "\t\tpop \tr1\n";
-                                output += "\t\ticmp\tr0, r1\n"; 
-                                output += &*format!("\t\tbne \t.label{}\n", labelindex);
-                                output += "\t\tmov \tr0, $0x00\n";
-                                output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                                output += &*format!(".label{}:\n", labelindex);
-                                output += "\t\tmov \tr0, $0x01\n";
-                                output += &*format!(".label_join{}:\n", labelindex);
-                                output += "\t\tpush\tr0\n";
-                                labelindex += 1;
+                                output += "\t\tcompare\ticmp, bne\n";
                             }
                             else {
                                 format_errora("`!=` expects two prior arguments. (eg. `1 2 !=`).".to_string());
                                 process::exit(1);
                             }
-                            labelindex += 1;
                         }
                         "d=" => {
                             if index >= 2 {
-                                output += "\t\tpop \tr0\n";
-                                output += "\t\tpop \tr1\n";
-                                output += "\t\tdcmp\tr0, r1\n"; 
-                                output += &*format!("\t\tbeq \t.label{}\n", labelindex);
-                                output += "\t\tmov \tr0, $0x00\n";
-                                output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                                output += &*format!(".label{}:\n", labelindex);
-                                output += "\t\tmov \tr0, $0x01\n";
-                                output += &*format!(".label_join{}:\n", labelindex);
-                                output += "\t\tpush\tr0\n";
-                                labelindex += 1;
+                                output += "\t\tcompare\tdcmp, beq\n";
                             } else {
                                 format_errora("`=` expects two prior arguments. (eg. `1 1 =`).".to_string());
                                 process::exit(1);
@@ -1026,23 +1834,12 @@ impl Lexer {
                         }
                         "d!=" => {
                             if index >= 2 {
-                                output += "\t\tpop \tr0\n";
-                                output += "\t\tpop \tr1\n";
-                                output += "\t\tdcmp\tr0, r1\n"; 
-                                output += &*format!("\t\tbne \t.label{}\n", labelindex);
-                                output += "\t\tmov \tr0, $0x00\n";
-                                output += &*format!("\t\tjmp \t.label_join{}\n", labelindex);
-                                output += &*format!(".label{}:\n", labelindex);
-                                output += "\t\tmov \tr0, $0x01\n";
-                                output += &*format!(".label_join{}:\n", labelindex);
-                                output += "\t\tpush\tr0\n";
-                                labelindex += 1;
+                                output += "\t\tcompare\tdcmp, bne\n";
                             }
                             else {
                                 format_errora("`!=` expects two prior arguments. (eg. `1 2 !=`).".to_string());
                                 process::exit(1);
                             }
-                            labelindex += 1;
                         }
                         "rot" => {
                             output += "\t\tpop \tr0\n";
@@ -1053,16 +1850,50 @@ impl Lexer {
                             output += "\t\tpush\tr2\n";
                         },
                         "while" => {
-                            loopmap_str.push(format!(".loop{}", loopindex));
+                            loopmap_str.push(format!("loop{}", loopindex));
                             loopmap_type.push(0);
-                            output += &*format!(".loop{}:", loopindex);
+                            output += &*format!("..loop{}:", loopindex);
                             loopindex += 1;
                         },
                         "if" => {
-                            loopmap_str.push(format!(".loop{}", loopindex));
+                            loopmap_str.push(format!("loop{}", loopindex));
                             loopmap_type.push(1);
                             loopindex += 1;
                         },
+                        // `try { ... } catch { ... }`: the handler is at
+                        // ..E_loopN and both blocks end up at ..loopN
+                        "try" => {
+                            loopmap_str.push(format!("loop{}", loopindex));
+                            loopmap_type.push(2);
+                            output += &*format!("\t\ttry \t..E_loop{}\n", loopindex);
+                            loopindex += 1;
+                        },
+                        "catch" => {
+                            if loopmap_type.last() != Some(&3) {
+                                format_errora("`catch` without a `try` block before it.".to_string());
+                                process::exit(1);
+                            }
+                        },
+                        "throw" => {
+                            output += "\t\tpop \tr0\n";
+                            output += "\t\tthrow\tr0\n";
+                        },
+                        "vector" => {
+                            if index + 1 < self.tokens.len() && self.tokens[index + 1].token_t == TokenType::LABEL {
+                                output += &*format!("\t\tadr \tr0, .{}\n", self.tokens[index + 1].data);
+                                output += "\t\tpush\tr0\n";
+                                output += "\t\tsys \t0x2f\n";
+                                index += 1;
+                            } else {
+                                format_errora("`vector` must be followed by the handler method (eg. `0 vector $on-tick`).".to_string());
+                                process::exit(1);
+                            }
+                        },
+                        "interrupt" => {
+                            output += "\t\tpop \tr0\n";
+                            output += "\t\tint \tr0\n";
+                        },
+                        "iret" => output += "\t\tiret\n",
                         "drop" => {
                             output += "\t\tpop \tr0\n";
                         },
@@ -1073,7 +1904,7 @@ impl Lexer {
                                     output += "\t\tpop \tr0\n";
                                     output += "\t\tmov \tr1, $0x01\n";
                                     output += "\t\ticmp\tr0, r1\n";
-                                    output += &*format!("\t\tbne \t.E_{}\n", last_entry);
+                                    output += &*format!("\t\tbne \t..E_{}\n", last_entry);
                                 }
                             }
                         },
@@ -1081,13 +1912,26 @@ impl Lexer {
                             if loopmap_str.len() > 0 {
                                 let last_entry: String = format!("{}", loopmap_str[loopmap_str.len()-1]);
                                 if loopmap_type[loopmap_type.len()-1] == 0 {
-                                    output += &*format!("\t\tjmp \t{}\n", last_entry);
-                                    output += &*format!(".E_{}:\n", last_entry);
+                                    output += &*format!("\t\tjmp \t..{}\n", last_entry);
+                                    output += &*format!("..E_{}:\n", last_entry);
                                     output += "\t\tpop \tr0\n";
                                     loopmap_str.pop();
                                     loopmap_type.pop();
+                                } else if loopmap_type[loopmap_type.len()-1] == 2 {
+                                    if index + 1 >= self.tokens.len() || self.tokens[index + 1].data != "catch" {
+                                        format_errora("`try` block must be followed by `catch { ... }`.".to_string());
+                                        process::exit(1);
+                                    }
+                                    output += "\t\tendtry\n";
+                                    output += &*format!("\t\tjmp \t..{}\n", last_entry);
+                                    output += &*format!("..E_{}:\n", last_entry);
+                                    *loopmap_type.last_mut().unwrap() = 3;
+                                } else if loopmap_type[loopmap_type.len()-1] == 3 {
+                                    output += &*format!("..{}:\n", last_entry);
+                                    loopmap_str.pop();
+                                    loopmap_type.pop();
                                 } else {
-                                    output += &*format!(".E_{}:\n", last_entry);
+                                    output += &*format!("..E_{}:\n", last_entry);
                                     loopmap_str.pop();
                                     loopmap_type.pop();
                                 }
@@ -1145,6 +1989,57 @@ impl Lexer {
                             output += "\t\tpop \tr1\n";
                             output += "\t\tbufc\tr0, r1\n";
                         },
+                        "alloc" => output += "\t\tsys \t0x0a\n",
+                        "free" => output += "\t\tsys \t0x0b\n",
+                        "spawn" => {
+                            if index + 1 < self.tokens.len() && self.tokens[index + 1].token_t == TokenType::LABEL {
+                                output += &*format!("\t\tadr \tr0, .{}\n", self.tokens[index + 1].data);
+                                output += "\t\tpush\tr0\n";
+                                output += "\t\tsys \t0x24\n";
+                                index += 1;
+                            } else {
+                                format_errora("`spawn` must be followed by the method to run (eg. `0 spawn $worker`).".to_string());
+                                process::exit(1);
+                            }
+                        },
+                        "thread" => {
+                            if index + 1 < self.tokens.len() && self.tokens[index + 1].token_t == TokenType::LABEL {
+                                output += &*format!("\t\tadr \tr0, .{}\n", self.tokens[index + 1].data);
+                                output += "\t\tpush\tr0\n";
+                                output += "\t\tsys \t0x2d\n";
+                                index += 1;
+                            } else {
+                                format_errora("`thread` must be followed by the method to run (eg. `0 thread $worker`).".to_string());
+                                process::exit(1);
+                            }
+                        },
+                        "atomic_load" => {
+                            output += "\t\tpop \tr1\n";
+                            output += "\t\tald \tr0, r1\n";
+                            output += "\t\tpush\tr0\n";
+                        },
+                        "atomic_store" => {
+                            output += "\t\tpop \tr0\n";
+                            output += "\t\tpop \tr1\n";
+                            output += "\t\tast \tr0, r1\n";
+                        },
+                        "atomic_cas" => {
+                            // addr expected new -- old
+                            output += "\t\tpop \tr1\n";
+                            output += "\t\tpop \tr0\n";
+                            output += "\t\tpop \tr2\n";
+                            output += "\t\tcas \tr1, r2\n";
+                            output += "\t\tpush\tr0\n";
+                        },
+                        "atomic_add" => {
+                            // addr n -- old
+                            output += "\t\tpop \tr0\n";
+                            output += "\t\tpop \tr1\n";
+                            output += "\t\txadd\tr0, r1\n";
+                            output += "\t\tpush\tr0\n";
+                        },
+                        "yield" => output += "\t\tsys \t0x25\n",
+                        "join" => output += "\t\tsys \t0x26\n",
                         "mem" => {
                             if index + 2 < self.tokens.len() {
                                 if self.tokens[index + 1].token_t == TokenType::INTEGER && self.tokens[index + 2].token_t == TokenType::VARIABLE_DECL {
@@ -1211,9 +2106,9 @@ impl Lexer {
                     }
                 },
                 TokenType::STRING => {
-                    output += &*format!("\t\tadr \tr0, .str{}\n", stringindex);
+                    output += &*format!("\t\tadr \tr0, .@str{}\n", stringindex);
                     output += "\t\tpush\tr0\n";
-                    stringmap.insert(format!(".str{}", stringindex), self.tokens[index].data.clone());
+                    stringmap.insert(format!(".@str{}", stringindex), self.tokens[index].data.clone());
                     stringindex += 1;
                 },
                 TokenType::METHOD => {
@@ -1221,6 +2116,9 @@ impl Lexer {
                         format_errora("Error while parsing - `method` without name found.".to_string());
                         process::exit(1);
                     }
+                    if self.relocatable() {
+                        output += &*format!(".global {}\n", self.tokens[index].data);
+                    }
                     output += &*format!(".{}:\n", self.tokens[index].data);
                     labels.push(format!("{}\n", self.tokens[index].data));
                 },
@@ -1243,12 +2141,17 @@ impl Lexer {
                     for var in varlist.iter() {
                         if var.variable_name == self.tokens[index].data {
                             match &var.variable_type {
+                                0 if self.relocatable() => {
+                                    // the linker places each object's `memory`
+                                    output += &*format!("\t\tadr \tr0, __memory + 0x{:x}\n", var.variable_data.parse::<usize>().unwrap());
+                                    output += "\t\tpush\tr0\n";
+                                },
                                 0 => {
                                     output += &*format!("\t\tadr \tr0, 0x{:x}\n", var.variable_data.parse::<usize>().unwrap());
                                     output += "\t\tpush\tr0\n";
                                 },
                                 1 => {
-                                    output += &*format!("\t\tadr \tr0, .{}\n", var.variable_name);
+                                    output += &*format!("\t\tadr \tr0, .@{}\n", var.variable_name);
                                     output += "\t\tpush\tr0\n";
                                 },
                                 2 => {
@@ -1272,6 +2175,7 @@ impl Lexer {
             }
             index += 1;
         }
+        self.vml_lines.extend(vec![origin; output[marked..].matches('\n').count()]);
         // be sure to jump to the end of the file in order to avoid executing the program's
         // data as code.
 
@@ -1285,10 +2189,34 @@ impl Lexer {
 
         for var in varlist.iter() {
             if var.variable_type == 1 {
-                output += &*format!(".{}: \"{}\"\n", var.variable_name, var.variable_data);
+                output += &*format!(".@{}: \"{}\"\n", var.variable_name, var.variable_data);
+            }
+        }
+        if self.relocatable() {
+            if memalloc > 0 {
+                output += &*format!(".memory 0x{:x}\n", memalloc);
             }
+            // number constants are exported for files that `import` this one
+            for var in varlist.iter() {
+                let value: u64 = match &var.variable_type {
+                    2 => var.variable_data.parse::<u64>().unwrap(),
+                    3 => var.variable_data.as_bytes()[0] as u64,
+                    4 => to_u64(var.variable_data.parse::<f64>().unwrap()),
+                    _ => continue
+                };
+                if var.variable_name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                    output += &*format!(".equ {} 0x{:x}\n.global {}\n", var.variable_name, value, var.variable_name);
+                }
+            }
+            self.tokens = Vec::new();
+            return output;
         }
-        output += ".end: jsr .main\n";
+        // the heap starts right after the static `memory` area.
+        output += ".end:\n";
+        output += &*format!("\t\tmov \tr0, $0x{:x}\n", memalloc);
+        output += "\t\tpush\tr0\n";
+        output += "\t\tsys \t0x0d\n";
+        output += "\t\tjsr \t.main\n";
         self.tokens = Vec::new();
         return output;
     }
@@ -1299,3 +2227,142 @@ impl Lexer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(directive: &str, operand: &str) -> Result<u64, String> {
+        parse_count(directive, &[operand.to_string()])
+    }
+
+    #[test]
+    fn align_takes_a_power_of_two() {
+        assert_eq!(count(".align", "1"), Ok(1));
+        assert_eq!(count(".align", "8"), Ok(8));
+        assert_eq!(count(".align", "0x1000"), Ok(0x1000));
+        assert_eq!(count(".align", "3"), Err("`.align` takes a power of two, found 3.".to_string()));
+        assert_eq!(count(".align", "12"), Err("`.align` takes a power of two, found 12.".to_string()));
+        assert!(count(".align", "0").is_err());
+        assert!(count(".align", "-8").is_err());
+        assert!(parse_count(".align", &["8".to_string(), "2".to_string()]).is_err());
+    }
+
+    fn constants(definitions: &[(&str, &str)]) -> HashMap<String, String> {
+        definitions.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn constants_in_terms_of_others() {
+        let defined = constants(&[("COUNT", "3"), ("SIZE", "COUNT * 8"), ("A", "5"), ("B", "2")]);
+        assert_eq!(evaluate_constant("SIZE / 2 + 1", &defined), Ok(13));
+        assert_eq!(evaluate_constant("A-B", &defined), Ok(3));
+        assert_eq!(evaluate_constant("__memory + 8", &defined), Ok(8));
+    }
+
+    #[test]
+    fn constants_defined_in_terms_of_themselves() {
+        let defined = constants(&[("X", "X + 1"), ("P", "Q * 2"), ("Q", "1 + P"), ("R", "Q")]);
+        assert_eq!(evaluate_constant("X", &defined), Err("`X` is defined in terms of itself.".to_string()));
+        assert_eq!(evaluate_constant("P", &defined), Err("`P` is defined in terms of itself.".to_string()));
+        assert_eq!(evaluate_constant("R", &defined), Err("`Q` is defined in terms of itself.".to_string()));
+    }
+
+    #[test]
+    fn relocations_of_operands() {
+        let labels: HashMap<String, usize> = [("start", 0), ("main", 0x20), ("end", 0x60)].iter()
+            .map(|(name, addr)| (name.to_string(), *addr))
+            .collect();
+        let defined = constants(&[("SIZE", "16"), ("TABLE", ".main + SIZE")]);
+        let relocate = |text: &str| relocate_operand(text, &labels, &defined);
+
+        assert_eq!(relocate("8").unwrap(), (8, None));
+        assert_eq!(relocate(".end - .start").unwrap(), (0x60, None));
+        assert_eq!(relocate("(.end - .main) / SIZE").unwrap(), (4, None));
+        assert_eq!(relocate(".main").unwrap(), (0x20, Some(Target::Code)));
+        assert_eq!(relocate("TABLE + 8").unwrap(), (0x38, Some(Target::Code)));
+        assert_eq!(relocate("__memory + 8").unwrap(), (8, Some(Target::Memory)));
+        assert_eq!(relocate(".helper").unwrap(), (0, Some(Target::Symbol("helper".to_string()))));
+        assert_eq!(relocate(".helper - 8").unwrap(), ((-8i64) as u64, Some(Target::Symbol("helper".to_string()))));
+
+        for unlinkable in [".main + .end", ".main * 2", "__memory + .main", ".helper + .other", ".helper - .main", ".main & 0xFF"] {
+            assert!(relocate(unlinkable).is_err(), "{} should not link", unlinkable);
+        }
+    }
+
+    #[test]
+    fn map_of_an_importing_program_is_linked() {
+        let dir = std::env::temp_dir();
+        let library = dir.join("vml-test-map-lib.o").to_string_lossy().to_string();
+        let output = dir.join("vml-test-map.bin").to_string_lossy().to_string();
+        let map = dir.join("vml-test-map.map").to_string_lossy().to_string();
+
+        let mut lexer = Lexer::new();
+        lexer.set_object();
+        lexer.set_output(library.clone());
+        lexer.lex_asm("\t.global helper\n.helper:\n\tret\n".to_string());
+        lexer.assemble_asm();
+
+        let mut lexer = Lexer::new();
+        lexer.set_output(output.clone());
+        lexer.set_map(map.clone());
+        lexer.lex_vml(format!("import \"{}\"\nmethod main {{\n    helper\n}}\n", library));
+        let assembly = lexer.tokens_to_assembly();
+        lexer.lex_asm(assembly);
+        let size = lexer.assemble_asm();
+        let contents = fs::read_to_string(&map).unwrap();
+        for file in [&library, &output, &map] {
+            fs::remove_file(file).unwrap();
+        }
+        fs::remove_file(Path::new(&output).with_extension("sym")).unwrap();
+
+        // address and size of each label
+        let entries: HashMap<&str, (usize, usize)> = contents.lines().skip(1)
+            .map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                (fields[2], (usize::from_str_radix(fields[0], 16).unwrap(), usize::from_str_radix(fields[1], 16).unwrap()))
+            })
+            .collect();
+        // the start-up code comes first, then the objects in order
+        assert_eq!(entries["start"], (0, 6));
+        assert_eq!(entries["main"].0, 6);
+        assert_eq!(entries["helper"], (entries["main"].0 + entries["main"].1, 2));
+        assert_eq!(entries["end"].0, entries["helper"].0 + 2);
+        assert_eq!(entries["end"].0 + entries["end"].1, size);
+    }
+
+    #[test]
+    fn zero_and_memory_take_any_positive_count() {
+        assert_eq!(count(".zero", "3"), Ok(3));
+        assert_eq!(count(".memory", "12"), Ok(12));
+        assert!(count(".zero", "0").is_err());
+    }
+
+    #[test]
+    fn compiled_code_maps_back_to_vml_lines() {
+        let dir = std::env::temp_dir();
+        let included = dir.join("vml-test-lines-inc.vml").to_string_lossy().to_string();
+        let output = dir.join("vml-test-lines.bin").to_string_lossy().to_string();
+        fs::write(&included, "let 7 const seven\nmethod boom {\n    seven 0 /\n}\n").unwrap();
+
+        let mut lexer = Lexer::new();
+        lexer.set_output(output.clone());
+        lexer.lex_vml(format!("include \"{}\"\n\nmethod main {{\n    1 2 +\n    boom\n}}\n", included));
+        let assembly = lexer.tokens_to_assembly();
+        lexer.lex_asm(assembly);
+        lexer.assemble_asm();
+        let info = DebugInfo::load(&Path::new(&output).with_extension("sym").to_string_lossy()).unwrap();
+        fs::remove_file(&included).unwrap();
+        fs::remove_file(&output).unwrap();
+        fs::remove_file(Path::new(&output).with_extension("sym")).unwrap();
+
+        let line_of = |method: &str| {
+            let addr = info.labels.iter().find(|(_, name)| name == method).unwrap().0;
+            info.lines.iter().find(|(a, line, _)| *a >= addr && *line != 0).map(|(_, line, text)| (*line, text.clone()))
+        };
+        assert_eq!(line_of("main"), Some((4, "1 2 +".to_string())));
+        assert_eq!(line_of("boom"), Some((3, format!("({}) seven 0 /", included))));
+        // the start-up code is the compiler's own
+        assert_eq!(info.line_for(0), None);
+    }
+}
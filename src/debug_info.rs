@@ -0,0 +1,92 @@
+use std::fs;
+
+// symbol and line information written next to a binary by the assembler.
+// the VM uses it to map a program counter back to a label and the line of
+// assembly that produced it.
+//
+// the file is plain text, one entry per line:
+//     L <address> <label>
+//     S <address> <line> <source text>
+
+#[derive(Default, Clone)]
+pub struct DebugInfo {
+    pub labels: Vec<(usize, String)>,
+    pub lines: Vec<(usize, usize, String)>
+}
+
+impl DebugInfo {
+    pub fn new() -> Self {
+        DebugInfo {
+            labels: Vec::new(),
+            lines: Vec::new()
+        }
+    }
+
+    pub fn load(filename: &str) -> Option<Self> {
+        let contents = fs::read_to_string(filename).ok()?;
+        let mut info = DebugInfo::new();
+
+        for entry in contents.lines() {
+            let mut fields = entry.splitn(4, ' ');
+            match fields.next() {
+                Some("L") => {
+                    let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+                    info.labels.push((addr, fields.next()?.to_string()));
+                },
+                Some("S") => {
+                    let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+                    let line = fields.next()?.parse::<usize>().ok()?;
+                    info.lines.push((addr, line, fields.next().unwrap_or("").to_string()));
+                },
+                _ => ()
+            }
+        }
+        info.labels.sort();
+        info.lines.sort();
+
+        Some(info)
+    }
+
+    pub fn write(self: &DebugInfo, filename: &str) {
+        let mut output: String = String::new();
+        for (addr, name) in &self.labels {
+            output += &format!("L {:x} {}\n", addr, name);
+        }
+        for (addr, line, text) in &self.lines {
+            output += &format!("S {:x} {} {}\n", addr, line, text.trim());
+        }
+        fs::write(filename, output).unwrap_or_else(|why| panic!("Couldn't write to file {}: {}", filename, why));
+    }
+
+    // returns the source line whose code contains `addr`. line 0 marks code
+    // the compiler added that has no line of its own.
+    pub fn line_for(self: &DebugInfo, addr: usize) -> Option<(usize, &str)> {
+        self.lines.iter()
+            .take_while(|(a, _, _)| *a <= addr)
+            .last()
+            .filter(|(_, line, _)| *line != 0)
+            .map(|(_, line, text)| (*line, text.as_str()))
+    }
+
+    // returns the method whose code contains `addr`, skipping over the
+    // control flow labels the compiler places inside method bodies.
+    pub fn method_for(self: &DebugInfo, addr: usize) -> Option<&str> {
+        let name = &self.labels.iter()
+            .take_while(|(a, _)| *a <= addr)
+            .filter(|(_, name)| !is_control_flow_label(name))
+            .last()?.1;
+        if name == "start" || name == "end" { None } else { Some(name) }
+    }
+}
+
+// `..loopN` and `..E_loopN` are generated by `tokens_to_assembly` for
+// `while`, `if` and `try`, and are local to the method like any `..name`.
+// `.name@N` are labels inside a macro expansion and numeric labels.
+// `.loopN`, `.E_.loopN`, `.labelN` and `.label_joinN` come from older
+// versions of the compiler.
+fn is_control_flow_label(name: &str) -> bool {
+    let numbered = |prefix: &str| {
+        name.strip_prefix(prefix).is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    };
+    name.starts_with("E_.") || name.contains("..") || name.contains('@') || numbered("loop") || numbered("label") || numbered("label_join")
+}
@@ -0,0 +1,276 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::errors::*;
+use crate::image::*;
+use crate::wav::*;
+
+// host devices backing ranges of addresses above the end of VM memory, so
+// that plain loads and stores (`@8`, `!32`, `sei`, `stt`, ...) reach them.
+
+pub const CONSOLE_BASE: usize = 0x08000000;
+pub const FRAMEBUFFER_BASE: usize = 0x08100000;
+// past the largest framebuffer, 16 + 4096 * 4096 * 4 bytes
+pub const AUDIO_BASE: usize = 0x0C200000;
+
+pub trait Device: Send {
+    fn size(&self) -> usize;
+    fn read(&self, offset: usize) -> u8;
+    fn write(&mut self, offset: usize, value: u8);
+    // called once when the program ends
+    fn finish(&mut self) {}
+}
+
+pub struct DeviceMap {
+    devices: Vec<(usize, Box<dyn Device>)>
+}
+
+impl DeviceMap {
+    pub fn new() -> Self {
+        DeviceMap {
+            devices: Vec::new()
+        }
+    }
+
+    // returns false, mapping nothing, when the range overlaps a device that
+    // is already mapped.
+    pub fn map(self: &mut DeviceMap, base: usize, device: Box<dyn Device>) -> bool {
+        let end = match base.checked_add(device.size()) {
+            Some(end) => end,
+            None => return false
+        };
+        if self.devices.iter().any(|(other, mapped)| base < other + mapped.size() && *other < end) {
+            return false;
+        }
+        self.devices.push((base, device));
+        true
+    }
+
+    pub fn read(self: &DeviceMap, addr: usize) -> Option<u8> {
+        self.devices.iter()
+            .find(|(base, device)| addr >= *base && addr < base + device.size())
+            .map(|(base, device)| device.read(addr - base))
+    }
+
+    // whether all `len` bytes at `addr` belong to a single device.
+    pub fn covers(self: &DeviceMap, addr: usize, len: usize) -> bool {
+        addr.checked_add(len).is_some_and(|end| {
+            self.devices.iter().any(|(base, device)| addr >= *base && end <= base + device.size())
+        })
+    }
+
+    // returns false when no device is mapped at `addr`.
+    pub fn write(self: &mut DeviceMap, addr: usize, value: u8) -> bool {
+        match self.devices.iter_mut().find(|(base, device)| addr >= *base && addr < *base + device.size()) {
+            Some((base, device)) => {
+                device.write(addr - *base, value);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn finish(self: &mut DeviceMap) {
+        for (_, device) in &mut self.devices {
+            device.finish();
+        }
+    }
+}
+
+impl Default for DeviceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// byte 0 writes a character to stdout, or reads one from stdin (0 once
+// stdin is exhausted).
+pub struct Console;
+
+impl Device for Console {
+    fn size(self: &Console) -> usize { 8 }
+
+    fn read(self: &Console, offset: usize) -> u8 {
+        let mut byte = [0u8; 1];
+        if offset != 0 || io::stdin().read(&mut byte).is_err() { return 0; }
+        byte[0]
+    }
+
+    fn write(self: &mut Console, offset: usize, value: u8) {
+        if offset != 0 { return; }
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[value]);
+        let _ = stdout.flush();
+    }
+}
+
+// layout:
+//     0   width (32-bit, read only)
+//     4   height (32-bit, read only)
+//     8   writing any byte here saves the image to the output file
+//     16  pixels, 32 bits each as 0x00RRGGBB, row by row from the top left
+pub const FRAMEBUFFER_PIXELS: usize = 16;
+
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+    output: String
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, output: String) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+            output
+        }
+    }
+
+    // writes a PPM when the output file ends in `.ppm` and a PNG otherwise.
+    fn dump(self: &Framebuffer) {
+        let rgb: Vec<u8> = self.pixels.chunks(4).flat_map(|p| [p[2], p[1], p[0]]).collect();
+        let result = match Path::new(&self.output).extension().and_then(|e| e.to_str()) {
+            Some("ppm") => write_ppm(&self.output, self.width, self.height, &rgb),
+            _ => write_png(&self.output, self.width, self.height, &rgb)
+        };
+        if let Err(why) = result {
+            warninga(&format!("couldn't write the framebuffer to {}: {}", self.output, why));
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn size(self: &Framebuffer) -> usize {
+        FRAMEBUFFER_PIXELS + self.pixels.len()
+    }
+
+    fn read(self: &Framebuffer, offset: usize) -> u8 {
+        match offset {
+            0..=3 => (self.width >> (offset * 8)) as u8,
+            4..=7 => (self.height >> ((offset - 4) * 8)) as u8,
+            FRAMEBUFFER_PIXELS.. => self.pixels[offset - FRAMEBUFFER_PIXELS],
+            _ => 0
+        }
+    }
+
+    fn write(self: &mut Framebuffer, offset: usize, value: u8) {
+        match offset {
+            8 => self.dump(),
+            FRAMEBUFFER_PIXELS.. => self.pixels[offset - FRAMEBUFFER_PIXELS] = value,
+            _ => ()
+        }
+    }
+
+    fn finish(self: &mut Framebuffer) {
+        self.dump();
+    }
+}
+
+// layout:
+//     0   16-bit sample; storing the high byte (offset 1) appends it
+//     8   64-bit float sample in [-1, 1]; storing its last byte appends it
+//     16  sample rate (32-bit, read only)
+//     20  number of samples appended so far (32-bit, read only)
+//     24  writing any byte here saves the samples to the output file
+pub struct Audio {
+    sample_rate: u32,
+    samples: Vec<i16>,
+    // the sample registers as they are being stored to
+    int_sample: [u8; 2],
+    float_sample: [u8; 8],
+    output: String
+}
+
+impl Audio {
+    pub fn new(sample_rate: u32, output: String) -> Self {
+        Audio {
+            sample_rate,
+            samples: Vec::new(),
+            int_sample: [0; 2],
+            float_sample: [0; 8],
+            output
+        }
+    }
+
+    fn save(self: &Audio) {
+        if let Err(why) = write_wav(&self.output, self.sample_rate, &self.samples) {
+            warninga(&format!("couldn't write the audio to {}: {}", self.output, why));
+        }
+    }
+}
+
+impl Device for Audio {
+    fn size(self: &Audio) -> usize { 32 }
+
+    fn read(self: &Audio, offset: usize) -> u8 {
+        match offset {
+            0..=1 => self.int_sample[offset],
+            8..=15 => self.float_sample[offset - 8],
+            16..=19 => (self.sample_rate >> ((offset - 16) * 8)) as u8,
+            20..=23 => ((self.samples.len() as u32) >> ((offset - 20) * 8)) as u8,
+            _ => 0
+        }
+    }
+
+    fn write(self: &mut Audio, offset: usize, value: u8) {
+        match offset {
+            0 => self.int_sample[0] = value,
+            1 => {
+                self.int_sample[1] = value;
+                self.samples.push(i16::from_le_bytes(self.int_sample));
+            },
+            8..=14 => self.float_sample[offset - 8] = value,
+            15 => {
+                self.float_sample[7] = value;
+                let sample = f64::from_le_bytes(self.float_sample);
+                // NaN saturates to silence
+                self.samples.push((sample.clamp(-1.0, 1.0) * i16::MAX as f64).round() as i16);
+            },
+            24 => self.save(),
+            _ => ()
+        }
+    }
+
+    fn finish(self: &mut Audio) {
+        self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_framebuffer_leaves_audio_alone() {
+        let mut devices = DeviceMap::new();
+        assert!(devices.map(CONSOLE_BASE, Box::new(Console)));
+        assert!(devices.map(FRAMEBUFFER_BASE, Box::new(Framebuffer::new(4096, 4096, String::new()))));
+        assert!(devices.map(AUDIO_BASE, Box::new(Audio::new(8000, String::new()))));
+        assert_eq!(devices.read(AUDIO_BASE + 17), Some((8000u32 >> 8) as u8));
+    }
+
+    #[test]
+    fn rejects_overlapping_devices() {
+        let mut devices = DeviceMap::new();
+        assert!(devices.map(AUDIO_BASE, Box::new(Audio::new(8000, String::new()))));
+        assert!(!devices.map(AUDIO_BASE + 31, Box::new(Console)));
+        assert!(!devices.map(AUDIO_BASE - 4, Box::new(Console)));
+        assert!(devices.map(AUDIO_BASE + 32, Box::new(Console)));
+        assert!(devices.map(AUDIO_BASE - 8, Box::new(Console)));
+        assert!(!devices.map(usize::MAX - 4, Box::new(Console)));
+    }
+
+    #[test]
+    fn ranges_within_one_device() {
+        let mut devices = DeviceMap::new();
+        assert!(devices.map(AUDIO_BASE, Box::new(Audio::new(8000, String::new()))));
+        assert!(devices.map(AUDIO_BASE + 32, Box::new(Console)));
+        assert!(devices.covers(AUDIO_BASE, 32));
+        assert!(devices.covers(AUDIO_BASE + 16, 4));
+        assert!(!devices.covers(AUDIO_BASE + 30, 4));
+        assert!(!devices.covers(AUDIO_BASE - 1, 2));
+        assert!(!devices.covers(AUDIO_BASE, usize::MAX));
+    }
+}
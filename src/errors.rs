@@ -1,9 +1,37 @@
 pub fn err_arg_not_found() -> &'static str {
-    return "ERROR::CMD_ARG_NOT_FOUND:\n\tUSAGE: vml [-C/-R/-A] [FILENAME]";
+    return "ERROR::CMD_ARG_NOT_FOUND:\n\tUSAGE: vml [-C/-R/-A] [FILENAME] [--sanitize] [--leaks] [--seed N] [--allow-net] [--allow-exec] [--framebuffer WxH] [--framebuffer-out FILE] [--audio RATE] [--audio-out FILE] [--listing FILE] [--map FILE] [--obj] [-o FILE] [-D NAME=VALUE] [-- ARGS...]\n\t       vml link [OBJECTS/ARCHIVES...] [-o FILE]\n\t       vml ar [OBJECTS...] [-o FILE]";
 }
 
 pub fn err_no_args() -> &'static str {
-    return "ERROR::NO_ARGS:\n\tUSAGE: vml [-C/-R/-A] [FILENAME]";
+    return "ERROR::NO_ARGS:\n\tUSAGE: vml [-C/-R/-A] [FILENAME] [--sanitize] [--leaks] [--seed N] [--allow-net] [--allow-exec] [--framebuffer WxH] [--framebuffer-out FILE] [--audio RATE] [--audio-out FILE] [--listing FILE] [--map FILE] [--obj] [-o FILE] [-D NAME=VALUE] [-- ARGS...]\n\t       vml link [OBJECTS/ARCHIVES...] [-o FILE]\n\t       vml ar [OBJECTS...] [-o FILE]";
+}
+
+pub fn err_bad_seed() -> &'static str {
+    "ERROR::BAD_SEED:\n\t--seed expects an unsigned integer, eg. `vml -r out.bin --seed 42`"
+}
+
+pub fn err_bad_framebuffer() -> &'static str {
+    "ERROR::BAD_FRAMEBUFFER:\n\t--framebuffer expects a size up to 4096x4096 and --framebuffer-out a filename, eg. `vml -r out.bin --framebuffer 320x200 --framebuffer-out picture.png`"
+}
+
+pub fn err_bad_audio() -> &'static str {
+    "ERROR::BAD_AUDIO:\n\t--audio expects a sample rate in Hz and --audio-out a filename, eg. `vml -r out.bin --audio 44100 --audio-out tone.wav`"
+}
+
+pub fn err_bad_listing() -> &'static str {
+    "ERROR::BAD_LISTING:\n\t--listing and --map expect a filename, eg. `vml -a prog.s --listing prog.lst --map prog.map`"
+}
+
+pub fn err_bad_output() -> &'static str {
+    "ERROR::BAD_OUTPUT:\n\t-o expects a filename, eg. `vml -c --obj a.vml -o a.o` or `vml link a.o b.o -o prog.bin`"
+}
+
+pub fn err_bad_define() -> &'static str {
+    "ERROR::BAD_DEFINE:\n\t-D expects a constant for the assembler, and only works with -a, eg. `vml -a stub.s -D DEBUG=1`"
+}
+
+pub fn err_no_objects() -> &'static str {
+    "ERROR::NO_OBJECTS:\n\tlink and ar expect the objects to put together, eg. `vml link a.o b.o -o prog.bin` or `vml ar a.o b.o -o lib.a`"
 }
 
 pub fn format_errorl(error: String, line: usize, error_block: String) {
@@ -17,6 +45,11 @@ pub fn format_errorl(error: String, line: usize, error_block: String) {
     println!("\x1b[93m\x1b[1mHere ->\t\x1b[21m\x1b[94m{}\x1b[0m", tildes);
 }
 
+pub fn format_notel(note: String, line: usize, block: String) {
+    println!("\x1b[36m\x1b[4mNote on line {}: {}\x1b[0m", line, note);
+    println!("\t{}", block);
+}
+
 pub fn format_errora(error: String) {
     println!("\x1b[31m\x1b[4mError while parsing: {}\x1b[0m", error);
 }
@@ -24,3 +57,17 @@ pub fn format_errora(error: String) {
 pub fn warninga(error: &str) {
     println!("\x1b[33m\x1b[4mWarning: {}\x1b[0m", error);
 }
+
+pub fn warningr(error: &str, pc: usize, source: Option<(usize, &str)>) {
+    eprintln!("\x1b[33m\x1b[4mWarning at pc {:#010x}: {}\x1b[0m", pc, error);
+    if let Some((line, text)) = source {
+        eprintln!("\tline {}: {}", line, text.trim());
+    }
+}
+
+pub fn format_errorr(error: &str, pc: usize, source: Option<(usize, &str)>) {
+    eprintln!("\x1b[31m\x1b[4mError at pc {:#010x}: {}\x1b[0m", pc, error);
+    if let Some((line, text)) = source {
+        eprintln!("\tline {}: {}", line, text.trim());
+    }
+}
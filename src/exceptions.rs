@@ -0,0 +1,54 @@
+use std::cell::Cell;
+use std::panic;
+
+// handler frames for `try`/`catch`. a frame is pushed by TRY and popped by
+// ENDTRY; THROW (or a runtime fault) unwinds to the innermost frame, cutting
+// both stacks back to the depths they had when the frame was pushed.
+
+pub struct Handler {
+    pub pc: usize,
+    pub stack_depth: usize,
+    pub return_depth: usize,
+    // interrupt handlers entered inside the `try` block are left as well
+    pub interrupt_depth: usize
+}
+
+// codes thrown by the VM itself. the top bit keeps them apart from the codes
+// programs throw.
+pub const EXC_DIVIDE_BY_ZERO: u64 = 0x8000000000000001;
+pub const EXC_MEMORY: u64 = 0x8000000000000002;
+pub const EXC_STACK_UNDERFLOW: u64 = 0x8000000000000003;
+pub const EXC_BAD_INSTRUCTION: u64 = 0x8000000000000004;
+pub const EXC_BAD_SYSCALL: u64 = 0x8000000000000005;
+pub const EXC_HEAP: u64 = 0x8000000000000006;
+
+// a runtime error raised from inside an instruction. it is thrown as a
+// panic payload so that it unwinds out of `run` from any depth.
+pub struct Fault {
+    pub code: u64,
+    pub reason: String
+}
+
+thread_local! {
+    // set while a thread is running VM code, whose faults `exec` catches and
+    // reports itself
+    static IN_VM: Cell<bool> = const { Cell::new(false) };
+}
+
+// installed once, before any VM runs. the hook is global to the process, so
+// swapping it per `exec` would race with VMs running on other threads.
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        // other panics are bugs, and keep their message and location
+        if !IN_VM.with(|flag| flag.get()) || !info.payload().is::<Fault>() {
+            default_hook(info);
+        }
+    }));
+}
+
+// marks the current thread as running VM code (or not), returning what it
+// was before.
+pub fn set_in_vm(value: bool) -> bool {
+    IN_VM.with(|flag| flag.replace(value))
+}
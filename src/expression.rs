@@ -0,0 +1,245 @@
+use crate::assembler::parse_literal;
+
+// operand expressions in assembly, eg. `.table + 8` or `(.end - .start) / 8`.
+// values are 64-bit and wrap, and shifts of 64 or more give 0; `.name` is the address of a label and a bare
+// name is a constant defined with `.equ`. from loosest to tightest binding
+// the operators are `|`, `&`, `<< >>`, `+ -` and `* /`, and a `-` before a
+// value negates it.
+
+#[derive(Debug, PartialEq)]
+enum Item {
+    Value(u64),
+    Label(String),
+    Constant(String),
+    Operator(&'static str),
+    Open,
+    Close
+}
+
+const OPERATORS: [&str; 8] = ["<<", ">>", "+", "-", "*", "/", "&", "|"];
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '@' || c == '.'
+}
+
+fn split_items(text: &str) -> Result<Vec<Item>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut items: Vec<Item> = Vec::new();
+    let mut index: usize = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let rest: String = chars[index..].iter().collect();
+        if c.is_whitespace() {
+            index += 1;
+        } else if c == '(' || c == ')' {
+            items.push(if c == '(' { Item::Open } else { Item::Close });
+            index += 1;
+        } else if let Some(operator) = OPERATORS.iter().find(|o| rest.starts_with(**o)) {
+            items.push(Item::Operator(operator));
+            index += operator.len();
+        } else if c == '\'' {
+            // a character, possibly escaped
+            let len = if chars.get(index + 1) == Some(&'\\') { 4 } else { 3 };
+            let literal: String = chars[index..(index + len).min(chars.len())].iter().collect();
+            match parse_literal(&literal) {
+                Some(value) => items.push(Item::Value(value)),
+                None => return Err(format!("`{}` is not a character.", literal))
+            }
+            index += len;
+        } else if is_symbol_char(c) {
+            // method names such as `.std-print` hold dashes, so in a label a
+            // `-` followed by a letter carries on the name rather than
+            // subtracting. constants are plain identifiers, so `A-B` subtracts
+            let label = c == '.';
+            let mut end = index + 1;
+            while end < chars.len() && (is_symbol_char(chars[end])
+                || (label && chars[end] == '-' && chars.get(end + 1).is_some_and(|n| n.is_alphabetic() || *n == '_'))) {
+                end += 1;
+            }
+            let word: String = chars[index..end].iter().collect();
+            if let Some(label) = word.strip_prefix('.') {
+                items.push(Item::Label(label.to_string()));
+            } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+                match parse_literal(&word) {
+                    Some(value) => items.push(Item::Value(value)),
+                    None => return Err(format!("`{}` is not a number, or does not fit in 64 bits.", word))
+                }
+            } else {
+                items.push(Item::Constant(word));
+            }
+            index = end;
+        } else {
+            return Err(format!("Unexpected `{}` in expression.", c));
+        }
+    }
+    Ok(items)
+}
+
+struct Parser<'a> {
+    items: Vec<Item>,
+    position: usize,
+    lookup: &'a mut dyn FnMut(&Item) -> Result<u64, String>
+}
+
+impl<'a> Parser<'a> {
+    // operators binding at `level`, loosest first
+    const LEVELS: [&'static [&'static str]; 5] = [&["|"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+    fn binary(self: &mut Parser<'a>, level: usize) -> Result<u64, String> {
+        if level == Self::LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(Item::Operator(operator)) = self.items.get(self.position) {
+            let operator: &str = operator;
+            if !Self::LEVELS[level].contains(&operator) { break; }
+            self.position += 1;
+            let rhs = self.binary(level + 1)?;
+            value = match operator {
+                "|" => value | rhs,
+                "&" => value & rhs,
+                // every bit is shifted out by 64 or more
+                "<<" => u32::try_from(rhs).ok().and_then(|rhs| value.checked_shl(rhs)).unwrap_or(0),
+                ">>" => u32::try_from(rhs).ok().and_then(|rhs| value.checked_shr(rhs)).unwrap_or(0),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                _ => match value.checked_div(rhs) {
+                    Some(result) => result,
+                    None => return Err("Division by zero in expression.".to_string())
+                }
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(self: &mut Parser<'a>) -> Result<u64, String> {
+        let item = match self.items.get(self.position) {
+            Some(item) => item,
+            None => return Err("Expression ends early.".to_string())
+        };
+        self.position += 1;
+        match item {
+            Item::Operator("-") => Ok(self.unary()?.wrapping_neg()),
+            Item::Value(value) => Ok(*value),
+            Item::Label(_) | Item::Constant(_) => (self.lookup)(&self.items[self.position - 1]),
+            Item::Open => {
+                let value = self.binary(0)?;
+                if self.items.get(self.position) != Some(&Item::Close) {
+                    return Err("Missing `)` in expression.".to_string());
+                }
+                self.position += 1;
+                Ok(value)
+            },
+            _ => Err("Expected a value in expression.".to_string())
+        }
+    }
+}
+
+// `labels` returns None for labels that do not exist, and `constants` the
+// value of a `.equ` name or why it has none.
+pub fn evaluate(text: &str,
+                labels: &mut dyn FnMut(&str) -> Option<u64>,
+                constants: &mut dyn FnMut(&str) -> Result<u64, String>) -> Result<u64, String> {
+    let mut lookup = |item: &Item| -> Result<u64, String> {
+        match item {
+            Item::Label(name) => labels(name).ok_or(format!("Label '{}' does not exist.", name)),
+            Item::Constant(name) => constants(name),
+            _ => unreachable!()
+        }
+    };
+    let mut parser = Parser { items: split_items(text)?, position: 0, lookup: &mut lookup };
+    if parser.items.is_empty() {
+        return Err("Empty expression.".to_string());
+    }
+    let value = parser.binary(0)?;
+    if parser.position != parser.items.len() {
+        return Err(format!("Unexpected `{}` in expression.", text));
+    }
+    Ok(value)
+}
+
+// whether `text` is a single label. these are read up to the next space, so
+// that every name the compiler gives a method (`.std-print`) works.
+pub fn is_plain_label(text: &str) -> bool {
+    text.strip_prefix('.').is_some_and(|l| !l.is_empty() && !l.contains(|c: char| c.is_whitespace() || "+*/&|<>()".contains(c)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<u64, String> {
+        let mut labels = |name: &str| match name {
+            "start" => Some(0x10),
+            "end" => Some(0x30),
+            "std-print" => Some(0x100),
+            "a" => Some(0x200),
+            _ => None
+        };
+        let mut constants = |name: &str| match name {
+            "A" => Ok(5),
+            "B" => Ok(2),
+            _ => Err(format!("`{}` is not defined.", name))
+        };
+        evaluate(text, &mut labels, &mut constants)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("16 / 4 / 2"), Ok(2));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("1 | 6 & 3"), Ok(3));
+        assert_eq!(eval("0xF0 >> 4 | 1 << 8"), Ok(0x10F));
+        assert_eq!(eval("-2 * 3"), Ok(-6i64 as u64));
+        assert_eq!(eval("- -1"), Ok(1));
+        assert_eq!(eval("(.end - .start) / 8"), Ok(4));
+    }
+
+    #[test]
+    fn wraps() {
+        assert_eq!(eval("0 - 1"), Ok(u64::MAX));
+        assert_eq!(eval("0xFFFFFFFFFFFFFFFF + 2"), Ok(1));
+    }
+
+    #[test]
+    fn wide_shifts() {
+        assert_eq!(eval("1 << 63"), Ok(1 << 63));
+        assert_eq!(eval("1 << 64"), Ok(0));
+        assert_eq!(eval("1 << 0x100000000"), Ok(0));
+        assert_eq!(eval("0xFF >> 64"), Ok(0));
+        assert_eq!(eval("0xFF >> 0x100000000"), Ok(0));
+        assert_eq!(eval("0 - 1 >> 63"), Ok(1));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0"), Err("Division by zero in expression.".to_string()));
+        assert_eq!(eval("1 / (A - 5)"), Err("Division by zero in expression.".to_string()));
+    }
+
+    #[test]
+    fn dashes_in_names() {
+        assert_eq!(eval("A-B"), Ok(3));
+        assert_eq!(eval("A - B"), Ok(3));
+        assert_eq!(eval("A-1"), Ok(4));
+        assert_eq!(eval(".std-print"), Ok(0x100));
+        assert_eq!(eval(".std-print+8"), Ok(0x108));
+        assert_eq!(eval(".a-b"), Err("Label 'a-b' does not exist.".to_string()));
+        assert_eq!(eval(".a - B"), Ok(0x1FE));
+        assert_eq!(eval(".a-2"), Ok(0x1FE));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(eval(""), Err("Empty expression.".to_string()));
+        assert_eq!(eval("1 +"), Err("Expression ends early.".to_string()));
+        assert_eq!(eval("(1 + 2"), Err("Missing `)` in expression.".to_string()));
+        assert_eq!(eval("1 2"), Err("Unexpected `1 2` in expression.".to_string()));
+        assert_eq!(eval("1 % 2"), Err("Unexpected `%` in expression.".to_string()));
+        assert_eq!(eval("C"), Err("`C` is not defined.".to_string()));
+    }
+}
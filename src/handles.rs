@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::thread::JoinHandle;
+
+// host resources owned by a running program, referred to from VML by small
+// integer handles.
+
+pub enum Handle {
+    File(File),
+    Listener(TcpListener),
+    Stream(TcpStream),
+    // a VM running on another host thread, finishing with the value it
+    // left on top of its stack
+    Thread(JoinHandle<u64>)
+}
+
+// status codes pushed by syscalls that can fail.
+pub const STATUS_OK: u64 = 0;
+pub const STATUS_NOT_FOUND: u64 = 1;
+pub const STATUS_PERMISSION_DENIED: u64 = 2;
+pub const STATUS_ALREADY_EXISTS: u64 = 3;
+pub const STATUS_INVALID: u64 = 4;
+pub const STATUS_BAD_HANDLE: u64 = 5;
+pub const STATUS_OTHER: u64 = 6;
+pub const STATUS_CLOSED: u64 = 7;
+pub const STATUS_EMPTY: u64 = 8;
+
+pub fn io_status(err: &io::Error) -> u64 {
+    match err.kind() {
+        io::ErrorKind::NotFound => STATUS_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => STATUS_PERMISSION_DENIED,
+        io::ErrorKind::AlreadyExists => STATUS_ALREADY_EXISTS,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => STATUS_INVALID,
+        _ => STATUS_OTHER
+    }
+}
+
+pub struct HandleTable {
+    handles: HashMap<u64, Handle>,
+    next: u64
+}
+
+impl HandleTable {
+    pub fn new() -> Self {
+        // 0 is never handed out so that it can be used as "no handle".
+        HandleTable {
+            handles: HashMap::new(),
+            next: 1
+        }
+    }
+
+    pub fn insert(self: &mut HandleTable, handle: Handle) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        self.handles.insert(id, handle);
+        id
+    }
+
+    // the id the next call to `insert` will hand out.
+    pub fn next_id(self: &HandleTable) -> u64 {
+        self.next
+    }
+
+    pub fn get(self: &mut HandleTable, id: u64) -> Option<&mut Handle> {
+        self.handles.get_mut(&id)
+    }
+
+    pub fn remove(self: &mut HandleTable, id: u64) -> Option<Handle> {
+        self.handles.remove(&id)
+    }
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
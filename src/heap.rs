@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+// first-fit allocator handing out blocks of VM memory for the `alloc`/`free`
+// syscalls. the heap lives above the static area that `memory` declarations
+// are laid out in, and every block is 8 byte aligned.
+
+pub struct Block {
+    pub size: usize,
+    // call sites leading up to the allocation, outermost first and ending
+    // with the pc of the syscall itself. used for leak reports.
+    pub site: Vec<usize>
+}
+
+pub struct Heap {
+    base: usize,
+    limit: usize,
+    pub blocks: BTreeMap<usize, Block>
+}
+
+// None when rounding up overflows, ie. for sizes no heap can hold.
+fn align(value: usize) -> Option<usize> {
+    Some(value.checked_add(7)? & !7)
+}
+
+impl Heap {
+    pub fn new(base: usize, limit: usize) -> Self {
+        Heap {
+            // a base past the end leaves no room, so every allocation fails
+            base: align(base.max(8)).unwrap_or(limit),
+            limit,
+            blocks: BTreeMap::new()
+        }
+    }
+
+    // moves the start of the heap. only possible before anything has been
+    // allocated.
+    pub fn set_base(self: &mut Heap, base: usize) -> bool {
+        if !self.blocks.is_empty() { return false; }
+        self.base = align(base.max(8)).unwrap_or(self.limit);
+        true
+    }
+
+    pub fn base(self: &Heap) -> usize {
+        self.base
+    }
+
+    // marks a range as taken without it being a real allocation, so that
+    // nothing is ever handed out on top of it.
+    pub fn reserve(self: &mut Heap, addr: usize, size: usize) {
+        self.blocks.insert(addr, Block { size: align(size).unwrap_or(size), site: Vec::new() });
+    }
+
+    pub fn alloc(self: &mut Heap, size: usize, site: Vec<usize>) -> Option<usize> {
+        let size = align(size.max(1))?;
+        let mut candidate = self.base;
+        for (addr, block) in &self.blocks {
+            if candidate.checked_add(size)? <= *addr { break; }
+            candidate = align(addr.checked_add(block.size)?)?;
+        }
+        if candidate.checked_add(size)? > self.limit { return None; }
+
+        self.blocks.insert(candidate, Block { size, site });
+        Some(candidate)
+    }
+
+    pub fn free(self: &mut Heap, addr: usize) -> Option<Block> {
+        self.blocks.remove(&addr)
+    }
+
+    pub fn size_of(self: &Heap, addr: usize) -> Option<usize> {
+        self.blocks.get(&addr).map(|block| block.size)
+    }
+
+    // grows or shrinks a block without moving it, if the space after it is
+    // free.
+    pub fn resize_in_place(self: &mut Heap, addr: usize, size: usize) -> bool {
+        let size = match align(size.max(1)) {
+            Some(size) => size,
+            None => return false
+        };
+        let next = self.blocks.range(addr + 1..).next().map_or(self.limit, |(a, _)| *a);
+        match self.blocks.get_mut(&addr) {
+            Some(block) if addr.checked_add(size).is_some_and(|end| end <= next) => {
+                block.size = size;
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_are_aligned_and_first_fit() {
+        let mut heap = Heap::new(3, 0x100);
+        assert_eq!(heap.base(), 8);
+        assert_eq!(heap.alloc(1, Vec::new()), Some(8));
+        assert_eq!(heap.alloc(9, Vec::new()), Some(16));
+        assert_eq!(heap.size_of(16), Some(16));
+        assert_eq!(heap.alloc(0, Vec::new()), Some(32));
+        assert_eq!(heap.size_of(32), Some(8));
+    }
+
+    #[test]
+    fn freed_blocks_are_split_and_coalesced() {
+        let mut heap = Heap::new(0, 0x100);
+        let a = heap.alloc(32, Vec::new()).unwrap();
+        let b = heap.alloc(32, Vec::new()).unwrap();
+        let c = heap.alloc(32, Vec::new()).unwrap();
+
+        // a smaller block takes the start of a free one, leaving the rest
+        heap.free(a).unwrap();
+        assert_eq!(heap.alloc(8, Vec::new()), Some(a));
+        assert_eq!(heap.alloc(24, Vec::new()), Some(a + 8));
+        assert_eq!(heap.alloc(8, Vec::new()), Some(c + 32));
+
+        // neighbouring free blocks make room for one larger than either
+        heap.free(b).unwrap();
+        heap.free(c).unwrap();
+        assert_eq!(heap.alloc(64, Vec::new()), Some(b));
+    }
+
+    #[test]
+    fn resize_in_place_needs_the_space_after() {
+        let mut heap = Heap::new(0, 0x40);
+        let a = heap.alloc(8, Vec::new()).unwrap();
+        let b = heap.alloc(8, Vec::new()).unwrap();
+
+        assert!(!heap.resize_in_place(a, 16));
+        assert!(heap.resize_in_place(b, 0x40 - b));
+        assert!(!heap.resize_in_place(b, 0x40 - b + 1));
+        assert!(heap.resize_in_place(b, 3));
+        assert_eq!(heap.size_of(b), Some(8));
+
+        heap.free(b).unwrap();
+        assert!(heap.resize_in_place(a, 24));
+        assert_eq!(heap.size_of(a), Some(24));
+        assert!(!heap.resize_in_place(0x30, 8));
+    }
+
+    #[test]
+    fn sizes_that_overflow_fail() {
+        let mut heap = Heap::new(0, usize::MAX);
+        let a = heap.alloc(8, Vec::new()).unwrap();
+        assert_eq!(heap.alloc(usize::MAX, Vec::new()), None);
+        assert_eq!(heap.alloc(usize::MAX - 3, Vec::new()), None);
+        assert!(!heap.resize_in_place(a, usize::MAX));
+        assert!(!heap.resize_in_place(a, usize::MAX - 8));
+
+        let mut full = Heap::new(usize::MAX, usize::MAX);
+        assert_eq!(full.alloc(1, Vec::new()), None);
+    }
+
+    #[test]
+    fn double_free_is_caught() {
+        let mut heap = Heap::new(0, 0x100);
+        let a = heap.alloc(8, Vec::new()).unwrap();
+        assert_eq!(heap.free(a).map(|block| block.size), Some(8));
+        assert!(heap.free(a).is_none());
+        assert!(heap.free(a + 8).is_none());
+        assert_eq!(heap.size_of(a), None);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+
+// encoders for dumping the framebuffer. `pixels` holds 3 bytes (red, green,
+// blue) per pixel, row by row from the top left.
+
+pub fn write_ppm(filename: &str, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    let mut output: Vec<u8> = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    output.extend_from_slice(pixels);
+    fs::write(filename, output)
+}
+
+// PNG with the image data in stored (uncompressed) deflate blocks, which
+// every decoder understands and needs no compressor.
+pub fn write_png(filename: &str, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    // every scanline starts with its filter type, 0 (none)
+    let mut raw: Vec<u8> = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolour, deflate, no filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut output: Vec<u8> = vec![0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    write_chunk(&mut output, b"IHDR", &header);
+    write_chunk(&mut output, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut output, b"IEND", &[]);
+    fs::write(filename, output)
+}
+
+fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = output.len();
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    let crc = crc32(&output[start..]);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary
+    let mut output: Vec<u8> = vec![0x78, 0x01];
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        output.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        output.push(last as u8);
+        output.extend_from_slice(&len.to_le_bytes());
+        output.extend_from_slice(&(!len).to_le_bytes());
+        output.extend_from_slice(block);
+    }
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFFFFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b): (u32, u32) = (1, 0);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"IEND"), 0xAE426082);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn zlib_splits_stored_blocks() {
+        assert_eq!(zlib_stored(&[]), vec![0x78, 0x01, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01]);
+
+        let data = vec![7u8; 70000];
+        let output = zlib_stored(&data);
+        assert_eq!(output.len(), 2 + 5 + 65535 + 5 + 4465 + 4);
+        assert_eq!(output[2..7], [0x00, 0xff, 0xff, 0x00, 0x00]);
+        assert_eq!(output[65542..65547], [0x01, 0x71, 0x11, 0x8e, 0xee]);
+        assert_eq!(output[output.len() - 4..], adler32(&data).to_be_bytes());
+    }
+
+    #[test]
+    fn writes_png() {
+        let filename = env::temp_dir().join("vml-test-write-png.png");
+        let filename = filename.to_str().unwrap();
+        write_png(filename, 2, 2, &[255, 0, 0, 0, 0, 255, 0, 255, 0, 10, 20, 30]).unwrap();
+        let expected: Vec<u8> = vec![
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d,
+            0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02,
+            0x08, 0x02, 0x00, 0x00, 0x00, 0xfd, 0xd4, 0x9a, 0x73, 0x00, 0x00, 0x00,
+            0x19, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x01, 0x0e, 0x00, 0xf1, 0xff,
+            0x00, 0xff, 0x00, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x0a,
+            0x14, 0x1e, 0x1a, 0x58, 0x03, 0x3a, 0xa9, 0x6b, 0x2c, 0x25, 0x00, 0x00,
+            0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82
+        ];
+        assert_eq!(fs::read(filename).unwrap(), expected);
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn writes_ppm() {
+        let filename = env::temp_dir().join("vml-test-write-ppm.ppm");
+        let filename = filename.to_str().unwrap();
+        write_ppm(filename, 1, 2, &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(fs::read(filename).unwrap(), b"P6\n1 2\n255\n\x01\x02\x03\x04\x05\x06");
+        fs::remove_file(filename).unwrap();
+    }
+}
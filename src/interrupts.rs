@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+// interrupts divert the VM to a handler registered in the vector table.
+// the timer and the host signal arrive between instructions; software
+// interrupts are raised with INT. the interrupted pc, flags and registers
+// are saved on entry and restored by IRET.
+
+pub const VECTORS: usize = 16;
+pub const IRQ_TIMER: usize = 0;
+pub const IRQ_SIGNAL: usize = 1;
+
+pub struct InterruptFrame {
+    pub pc: usize,
+    pub flags: u8,
+    pub registers: Vec<u64>
+}
+
+pub enum Timer {
+    Off,
+    // fires every `period` instructions
+    Instructions { period: u64, count: u64 },
+    // fires every `period` of wall clock time
+    Millis { period: Duration, next: Instant }
+}
+
+impl Timer {
+    pub fn new(mode: u64, period: u64) -> Option<Self> {
+        match mode {
+            0 => Some(Timer::Off),
+            1 if period > 0 => Some(Timer::Instructions { period, count: 0 }),
+            2 if period > 0 => {
+                let period = Duration::from_millis(period);
+                Some(Timer::Millis { period, next: Instant::now() + period })
+            },
+            _ => None
+        }
+    }
+
+    // called once per instruction. returns true when the timer fires.
+    pub fn tick(self: &mut Timer) -> bool {
+        match self {
+            Timer::Off => false,
+            Timer::Instructions { period, count } => {
+                *count += 1;
+                if *count < *period { return false; }
+                *count = 0;
+                true
+            },
+            Timer::Millis { period, next } => {
+                let now = Instant::now();
+                if now < *next { return false; }
+                *next = now + *period;
+                true
+            }
+        }
+    }
+}
+
+// set by the host signal handler, taken by whichever VM polls it first.
+static SIGNAL_RAISED: AtomicBool = AtomicBool::new(false);
+
+pub fn take_signal() -> bool {
+    SIGNAL_RAISED.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+mod host {
+    use std::sync::atomic::Ordering;
+
+    const SIGINT: i32 = 2;
+    const SIG_DFL: usize = 0;
+
+    extern "C" {
+        fn signal(signum: i32, handler: usize) -> usize;
+    }
+
+    extern "C" fn on_signal(_: i32) {
+        super::SIGNAL_RAISED.store(true, Ordering::SeqCst);
+    }
+
+    // routes Ctrl-C to the VM instead of ending the process, or restores
+    // the default behaviour.
+    pub fn catch_interrupt(enable: bool) {
+        let handler = if enable { on_signal as extern "C" fn(i32) as usize } else { SIG_DFL };
+        unsafe { signal(SIGINT, handler); }
+    }
+}
+
+#[cfg(not(unix))]
+mod host {
+    pub fn catch_interrupt(_: bool) {}
+}
+
+pub use host::catch_interrupt;
@@ -0,0 +1,464 @@
+use crate::errors::*;
+use crate::debug_info::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::process;
+
+// relocatable objects written by `vml -c --obj` and `vml -a --obj`, and the
+// linker that puts them together into a binary.
+//
+// objects are plain text, one entry per line:
+//     VMLOBJ 1
+//     M <static memory size>
+//     A <alignment>                  the strictest `.align` in the code
+//     G <offset> <name>              a label other objects can use
+//     K <name> <value>               a constant for files that `import` it
+//     R <offset> <width>             add the address of the object's code
+//     B <offset> <width>             add the address of its static memory
+//     X <offset> <width> <name>      add the address of a label in another object
+//     L <offset> <label>
+//     S <offset> <line> <source text>
+//     C <code bytes in hex>
+// numbers are hexadecimal apart from line numbers, and the value already at
+// a patched offset is added to, so `.table + 8` keeps its 8.
+
+const MAGIC: &str = "VMLOBJ 1";
+// code bytes per `C` line
+const BYTES_PER_LINE: usize = 32;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Code,
+    Memory,
+    Symbol(String)
+}
+
+#[derive(Clone)]
+pub struct Relocation {
+    pub offset: usize,
+    pub width: usize,
+    pub target: Target
+}
+
+#[derive(Clone)]
+pub struct Object {
+    pub name: String,
+    pub code: Vec<u8>,
+    // bytes of static memory, ie. `memory` variables
+    pub memory: usize,
+    // the code is placed at a multiple of this, so that its `.align`s hold
+    pub align: usize,
+    pub globals: Vec<(String, usize)>,
+    pub constants: Vec<(String, u64)>,
+    pub relocations: Vec<Relocation>,
+    pub debug: DebugInfo
+}
+
+impl Object {
+    pub fn new(name: &str) -> Self {
+        Object {
+            name: name.to_string(),
+            code: Vec::new(),
+            memory: 0,
+            align: 1,
+            globals: Vec::new(),
+            constants: Vec::new(),
+            relocations: Vec::new(),
+            debug: DebugInfo::new()
+        }
+    }
+
+    // labels in other objects that this one uses.
+    pub fn imports(self: &Object) -> Vec<&str> {
+        let mut names: Vec<&str> = self.relocations.iter()
+            .filter_map(|r| match &r.target {
+                Target::Symbol(name) => Some(name.as_str()),
+                _ => None
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn to_text(self: &Object) -> String {
+        let mut output: String = format!("{}\nM {:x}\n", MAGIC, self.memory);
+        if self.align > 1 {
+            output += &format!("A {:x}\n", self.align);
+        }
+        for (name, offset) in &self.globals {
+            output += &format!("G {:x} {}\n", offset, name);
+        }
+        for (name, value) in &self.constants {
+            output += &format!("K {} {:x}\n", name, value);
+        }
+        for relocation in &self.relocations {
+            output += &match &relocation.target {
+                Target::Code => format!("R {:x} {}\n", relocation.offset, relocation.width),
+                Target::Memory => format!("B {:x} {}\n", relocation.offset, relocation.width),
+                Target::Symbol(name) => format!("X {:x} {} {}\n", relocation.offset, relocation.width, name)
+            };
+        }
+        for (addr, name) in &self.debug.labels {
+            output += &format!("L {:x} {}\n", addr, name);
+        }
+        for (addr, line, text) in &self.debug.lines {
+            output += &format!("S {:x} {} {}\n", addr, line, text.trim());
+        }
+        for chunk in self.code.chunks(BYTES_PER_LINE) {
+            output += "C ";
+            output += &chunk.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+            output += "\n";
+        }
+        output
+    }
+
+    // None if `text` is not an object.
+    pub fn from_text(name: &str, text: &str) -> Option<Self> {
+        let mut entries = text.lines();
+        if entries.next()? != MAGIC {
+            return None;
+        }
+        let mut object = Object::new(name);
+        let hex = |field: Option<&str>| usize::from_str_radix(field?, 16).ok();
+        for entry in entries {
+            let mut fields = entry.splitn(4, ' ');
+            match fields.next()? {
+                "M" => object.memory = hex(fields.next())?,
+                "A" => object.align = hex(fields.next()).filter(|a| a.is_power_of_two())?,
+                "G" => {
+                    let offset = hex(fields.next())?;
+                    object.globals.push((fields.next()?.to_string(), offset));
+                },
+                "K" => {
+                    let name = fields.next()?.to_string();
+                    object.constants.push((name, u64::from_str_radix(fields.next()?, 16).ok()?));
+                },
+                kind @ ("R" | "B" | "X") => {
+                    let offset = hex(fields.next())?;
+                    let width = fields.next()?.parse::<usize>().ok().filter(|w| *w == 4 || *w == 8)?;
+                    let target = match kind {
+                        "R" => Target::Code,
+                        "B" => Target::Memory,
+                        _ => Target::Symbol(fields.next()?.to_string())
+                    };
+                    object.relocations.push(Relocation { offset, width, target });
+                },
+                "L" => {
+                    let addr = hex(fields.next())?;
+                    object.debug.labels.push((addr, fields.next()?.to_string()));
+                },
+                "S" => {
+                    let addr = hex(fields.next())?;
+                    let line = fields.next()?.parse::<usize>().ok()?;
+                    object.debug.lines.push((addr, line, fields.next().unwrap_or("").to_string()));
+                },
+                "C" => {
+                    let bytes = fields.next()?;
+                    for index in (0..bytes.len()).step_by(2) {
+                        object.code.push(u8::from_str_radix(bytes.get(index..index + 2)?, 16).ok()?);
+                    }
+                },
+                _ => return None
+            }
+        }
+        if object.relocations.iter().any(|r| r.offset.checked_add(r.width).is_none_or(|end| end > object.code.len())) {
+            return None;
+        }
+        Some(object)
+    }
+
+    pub fn load(filename: &str) -> Self {
+        let text = match fs::read_to_string(filename) {
+            Ok(text) => text,
+            Err(why) => {
+                format_errora(format!("Couldn't read `{}`: {}", filename, why));
+                process::exit(1);
+            }
+        };
+        match Object::from_text(filename, &text) {
+            Some(object) => object,
+            None => {
+                format_errora(format!("`{}` is not a VML object file.", filename));
+                process::exit(1);
+            }
+        }
+    }
+}
+
+// the same start-up code a compiled program has: `.start` jumps over the
+// objects to `.end`, which starts the heap past every object's static memory
+// and calls `main`. the program finishes when `main` returns off the end.
+//     .start: jmp .end
+//     ...
+//     .end:   mov r0, $<memory>
+//             push r0
+//             sys 0x0d
+//             jsr .main
+fn startup(memory: usize) -> (Object, Object) {
+    let mut start = Object::new("<start-up>");
+    start.code.extend_from_slice(&[0x19, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    start.relocations.push(Relocation { offset: 2, width: 4, target: Target::Symbol("end".to_string()) });
+    start.globals.push(("start".to_string(), 0));
+    start.debug.labels.push((0, "start".to_string()));
+
+    let mut end = Object::new("<start-up>");
+    end.code.extend_from_slice(&[0x00, 0x00]);
+    end.code.extend_from_slice(&(memory as u64).to_le_bytes());
+    end.code.extend_from_slice(&[0x06, 0x00]);
+    end.code.extend_from_slice(&[0x20, 0x00, 0x0d, 0x00, 0x00, 0x00]);
+    end.code.extend_from_slice(&[0x1e, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    end.relocations.push(Relocation { offset: end.code.len() - 4, width: 4, target: Target::Symbol("main".to_string()) });
+    end.globals.push(("end".to_string(), 0));
+    end.debug.labels.push((0, "end".to_string()));
+    (start, end)
+}
+
+fn fail(error: String) -> ! {
+    format_errora(error);
+    process::exit(1);
+}
+
+// lays the objects out one after the other within the start-up code, with
+// their static memory likewise, and patches every relocation. code is padded
+// with zeros up to each object's alignment. returns the binary and its debug
+// information.
+pub fn link(objects: &[Object]) -> (Vec<u8>, DebugInfo) {
+    let memory: usize = objects.iter().map(|o| o.memory).sum();
+    let (start, end) = startup(memory);
+    let mut all: Vec<Object> = vec![start];
+    all.extend_from_slice(objects);
+    all.push(end);
+
+    let mut code_base: Vec<usize> = Vec::new();
+    let mut memory_base: Vec<usize> = Vec::new();
+    let (mut code, mut data): (usize, usize) = (0, 0);
+    for object in &all {
+        code = match code.checked_next_multiple_of(object.align) {
+            Some(code) => code,
+            None => fail(format!("`{}` cannot be aligned to {:#x} bytes.", object.name, object.align))
+        };
+        code_base.push(code);
+        memory_base.push(data);
+        code += object.code.len();
+        data += object.memory;
+    }
+
+    let mut symbols: HashMap<&str, (usize, &str)> = HashMap::new();
+    for (index, object) in all.iter().enumerate() {
+        for (name, offset) in &object.globals {
+            if let Some((_, other)) = symbols.insert(name, (code_base[index] + offset, &object.name)) {
+                fail(format!("`{}` is defined in both `{}` and `{}`.", name, other, object.name));
+            }
+        }
+    }
+
+    let mut binary: Vec<u8> = Vec::with_capacity(code);
+    let mut debug_info = DebugInfo::new();
+    for (index, object) in all.iter().enumerate() {
+        let base = code_base[index];
+        let mut bytes = object.code.clone();
+        for relocation in &object.relocations {
+            let add = match &relocation.target {
+                Target::Code => base,
+                Target::Memory => memory_base[index],
+                Target::Symbol(name) => match symbols.get(name.as_str()) {
+                    Some((addr, _)) => *addr,
+                    None => fail(format!("`{}` uses `{}`, which no object defines.", object.name, name))
+                }
+            };
+            let slot = &mut bytes[relocation.offset..relocation.offset + relocation.width];
+            let mut value = [0u8; 8];
+            value[..relocation.width].copy_from_slice(slot);
+            let mut value = u64::from_le_bytes(value);
+            if relocation.width < 8 && value >> (relocation.width * 8 - 1) != 0 {
+                // a negative addend, eg. `.table - 8`
+                value |= u64::MAX << (relocation.width * 8);
+            }
+            let value = value.wrapping_add(add as u64);
+            if relocation.width < 8 && value >> (relocation.width * 8) != 0 {
+                fail(format!("The address patched in at {:#x} of `{}` does not fit in {} bytes.",
+                             relocation.offset, object.name, relocation.width));
+            }
+            slot.copy_from_slice(&value.to_le_bytes()[..relocation.width]);
+        }
+        binary.resize(base, 0);
+        binary.extend_from_slice(&bytes);
+
+        for (addr, name) in &object.debug.labels {
+            debug_info.labels.push((base + addr, name.clone()));
+        }
+        for (addr, line, text) in &object.debug.lines {
+            debug_info.lines.push((base + addr, *line, text.clone()));
+        }
+    }
+    debug_info.labels.sort();
+    debug_info.lines.sort();
+    (binary, debug_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Lexer;
+    use std::env;
+
+    // the main object: code and memory relocations, an import with a
+    // negative addend and 4 and 8 byte patches.
+    const MAIN: &str = "\
+        .global main
+        .memory 16
+.main:  adr     r0, __memory + 8
+        adr     r1, .helper - 8
+        mov     r2, .helper + 16
+        mov     r3, .table + 8
+        jsr     .helper
+        ret
+.table: .quad   .helper, .main, 7
+";
+
+    const HELPER: &str = "\
+        .global helper
+.helper:
+        adr     r4, .data
+        adr     r5, .main - 4
+        ret
+.data:  .dword  .helper + 2, 9
+";
+
+    // assembles `source` into `name` in the temporary directory and returns
+    // what was written.
+    fn assemble(name: &str, source: &str, object: bool) -> Vec<u8> {
+        let filename = env::temp_dir().join(name).to_string_lossy().to_string();
+        let mut lexer = Lexer::new();
+        if object {
+            lexer.set_object();
+        }
+        lexer.set_output(filename.clone());
+        lexer.lex_asm(source.to_string());
+        lexer.assemble_asm();
+        let output = fs::read(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        let _ = fs::remove_file(env::temp_dir().join(name).with_extension("sym"));
+        output
+    }
+
+    fn object(name: &str, source: &str) -> Object {
+        let text = String::from_utf8(assemble(name, source, true)).unwrap();
+        Object::from_text(name, &text).unwrap()
+    }
+
+    #[test]
+    fn link_matches_whole_program() {
+        // the same start-up code the linker puts around the objects
+        let whole = format!(".start: jmp .end\n{}{}.end:   mov r0, $16\n        push r0\n        sys 0x0d\n        jsr .main\n", MAIN, HELPER);
+        let expected = assemble("vml-test-link-whole.bin", &whole, false);
+
+        let main = object("vml-test-link-main.o", MAIN);
+        let helper = object("vml-test-link-helper.o", HELPER);
+        assert_eq!(main.imports(), vec!["helper"]);
+        assert_eq!(helper.imports(), vec!["main"]);
+        let (binary, debug) = link(&[main.clone(), helper.clone()]);
+        assert_eq!(binary, expected);
+
+        let label = |name: &str| debug.labels.iter().find(|(_, l)| l == name).map(|(addr, _)| *addr);
+        assert_eq!(label("main"), Some(6));
+        assert_eq!(label("helper"), Some(6 + main.code.len()));
+        assert_eq!(label("end"), Some(6 + main.code.len() + helper.code.len()));
+    }
+
+    #[test]
+    fn link_places_memory_after_earlier_objects() {
+        let main = object("vml-test-memory-main.o", MAIN);
+        let other = object("vml-test-memory-other.o", ".global helper\n.memory 8\n.helper: adr r0, __memory + 4\n        ret\n");
+        let (binary, _) = link(&[main.clone(), other.clone()]);
+
+        // `adr r0, __memory + 4` in the second object, after the main one's 16 bytes
+        let at = 6 + main.code.len() + 2;
+        assert_eq!(binary[at..at + 4], 20u32.to_le_bytes());
+        // and the heap starts after both
+        let end = 6 + main.code.len() + other.code.len();
+        assert_eq!(binary[end + 2..end + 10], 24u64.to_le_bytes());
+    }
+
+    #[test]
+    fn aligned_objects_are_padded() {
+        let main = object("vml-test-align-main.o", MAIN);
+        let table = object("vml-test-align-table.o", "\
+        .global helper
+.helper: ret
+        .align 16
+.words: .quad .words
+        .align 8
+");
+        assert_eq!(table.align, 16);
+        assert_eq!(Object::from_text("again.o", &table.to_text()).unwrap().align, 16);
+
+        let (binary, debug) = link(&[main.clone(), table.clone()]);
+        let label = |name: &str| debug.labels.iter().find(|(_, l)| l == name).map(|(addr, _)| *addr).unwrap();
+        let base = (6 + main.code.len()).next_multiple_of(16);
+        assert_eq!(label("helper"), base);
+        assert_eq!(label("words") % 16, 0);
+        assert_eq!(binary[label("words")..label("words") + 8], (label("words") as u64).to_le_bytes());
+        assert!(binary[6 + main.code.len()..base].iter().all(|b| *b == 0));
+        assert_eq!(label("end"), base + table.code.len());
+    }
+
+    #[test]
+    fn negative_addends_are_sign_extended() {
+        let mut user = Object::new("user.o");
+        user.code = vec![0xF8, 0xFF, 0xFF, 0xFF, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        user.relocations.push(Relocation { offset: 0, width: 4, target: Target::Symbol("main".to_string()) });
+        user.relocations.push(Relocation { offset: 4, width: 8, target: Target::Symbol("main".to_string()) });
+        let mut main = Object::new("main.o");
+        main.code = vec![0; 16];
+        main.globals.push(("main".to_string(), 8));
+
+        let (binary, _) = link(&[user.clone(), main]);
+        // main is at 6 (start-up) + 12 (user) + 8
+        let main_addr: u64 = 26;
+        assert_eq!(binary[6..10], ((main_addr - 8) as u32).to_le_bytes());
+        assert_eq!(binary[10..18], (main_addr - 8).to_le_bytes());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let main = object("vml-test-round-trip.o", MAIN);
+        assert_eq!(main.memory, 16);
+        assert_eq!(main.globals, vec![("main".to_string(), 0)]);
+        assert!(main.relocations.iter().any(|r| r.target == Target::Memory && r.width == 4));
+        assert!(main.relocations.iter().any(|r| r.target == Target::Code && r.width == 8));
+        assert!(main.relocations.iter().any(|r| r.target == Target::Symbol("helper".to_string()) && r.width == 8));
+
+        let mut constants = main.clone();
+        constants.constants.push(("SIZE".to_string(), 0x40));
+        let text = constants.to_text();
+        let read = Object::from_text("again.o", &text).unwrap();
+        assert_eq!(read.to_text(), text);
+        assert_eq!(read.code, main.code);
+        assert_eq!(read.constants, vec![("SIZE".to_string(), 0x40)]);
+        assert_eq!(read.debug.labels, main.debug.labels);
+        assert_eq!(read.debug.lines.len(), main.debug.lines.len());
+    }
+
+    #[test]
+    fn corrupt_objects_are_rejected() {
+        let valid = "VMLOBJ 1\nM 0\nR 0 4\nC 0000000000\n";
+        assert!(Object::from_text("a.o", valid).is_some());
+        for corrupt in [
+            "VMLOBJ 2\nM 0\n",
+            "VMLOBJ 1\nM zz\n",
+            "VMLOBJ 1\nR 2 4\nC 0000000000\n",
+            "VMLOBJ 1\nR 0 3\nC 0000000000\n",
+            "VMLOBJ 1\nR fffffffffffffffe 4\nC 0000000000\n",
+            "VMLOBJ 1\nX 0 4\nC 0000000000\n",
+            "VMLOBJ 1\nC 000\n",
+            "VMLOBJ 1\nA 3\n",
+            "VMLOBJ 1\nA 0\n",
+            "VMLOBJ 1\nQ 0\n"
+        ] {
+            assert!(Object::from_text("a.o", corrupt).is_none(), "{:?} should be rejected", corrupt);
+        }
+    }
+}
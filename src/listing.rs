@@ -0,0 +1,146 @@
+use crate::macros::SourceLine;
+
+use std::fs;
+use std::io;
+
+// bytes shown on each line of a listing; longer lines carry on below.
+const BYTES_PER_ROW: usize = 8;
+
+fn listing_row(address: Option<usize>, bytes: &[u8], line: usize, marker: char, text: &str) -> String {
+    let address = match address {
+        Some(address) => format!("{:08x}", address),
+        None => " ".repeat(8)
+    };
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}  {:<24} {:>5}{} {}\n", address, bytes.join(" "), line, marker, text)
+}
+
+// every line of the file with the address and the bytes its code ended up
+// as. lines produced by a macro follow its invocation, marked with a `+`.
+// `ranges` holds the start and end address of each line in `source`, for
+// lines that produced anything.
+pub fn write_listing(filename: &str, file_lines: &[String], source: &[SourceLine],
+                     ranges: &[Option<(usize, usize)>], output: &[u8]) -> io::Result<()> {
+    let mut listing: String = format!("{:<10}{:<25}{:>5}  {}\n", "; address", "bytes", "line", "source");
+    let mut next_line: usize = 0;
+    let empty = |line: usize| listing_row(None, &[], line + 1, ' ', &file_lines[line]);
+
+    for (origin, range) in source.iter().zip(ranges) {
+        let file_line = origin.file_line();
+        // lines without code of their own, eg. macro definitions
+        while next_line < file_line.min(file_lines.len()) {
+            listing += &empty(next_line);
+            next_line += 1;
+        }
+        let (line, marker, text) = if origin.expanded_from.is_empty() {
+            (file_line, ' ', &origin.text)
+        } else {
+            if next_line == file_line {
+                listing += &empty(file_line);
+            }
+            (origin.line, '+', &origin.text)
+        };
+        next_line = next_line.max(file_line + 1);
+
+        match range {
+            Some((start, end)) => {
+                let bytes = &output[*start..*end];
+                let mut rows = bytes.chunks(BYTES_PER_ROW);
+                listing += &listing_row(Some(*start), rows.next().unwrap_or(&[]), line + 1, marker, text);
+                for (row, chunk) in rows.enumerate() {
+                    listing += &format!("{:08x}  {}\n", start + (row + 1) * BYTES_PER_ROW,
+                                        chunk.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" "));
+                }
+            },
+            None => listing += &listing_row(None, &[], line + 1, marker, text)
+        }
+    }
+    while next_line < file_lines.len() {
+        listing += &empty(next_line);
+        next_line += 1;
+    }
+    fs::write(filename, listing)
+}
+
+// every label with its address and size, the distance to the next label (or
+// the end of the binary), then the `.equ` constants.
+pub fn write_map(filename: &str, labels: &[(usize, String)], constants: &[(String, String)],
+                 end: usize) -> io::Result<()> {
+    let mut sorted: Vec<&(usize, String)> = labels.iter().collect();
+    sorted.sort();
+
+    let mut map: String = format!("{:<10}{:<10}{}\n", "; address", "size", "label");
+    for (index, (addr, name)) in sorted.iter().enumerate() {
+        let next = sorted[index + 1..].iter()
+            .map(|(next, _)| *next)
+            .find(|next| next > addr)
+            .unwrap_or(end.max(*addr));
+        map += &format!("{:08x}  {:08x}  {}\n", addr, next - addr, name);
+    }
+    if !constants.is_empty() {
+        map += "\n; constant  value\n";
+        for (name, value) in constants {
+            map += &format!("{} = {}\n", name, value);
+        }
+    }
+    fs::write(filename, map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macros::preprocess;
+    use std::env;
+
+    // runs `write` on a file in the temporary directory and returns what it
+    // wrote.
+    fn written(name: &str, write: impl FnOnce(&str) -> io::Result<()>) -> String {
+        let filename = env::temp_dir().join(name).to_string_lossy().to_string();
+        write(&filename).unwrap();
+        let contents = fs::read_to_string(&filename).unwrap();
+        fs::remove_file(&filename).unwrap();
+        contents
+    }
+
+    #[test]
+    fn listing_shows_addresses_bytes_and_expansions() {
+        let text = ".macro two\n\tnop\n\tnop\n.endm\n.start:\ttwo\n\t.byte 1, 2, 3, 4, 5, 6, 7, 8, 9, 10\n; done\n";
+        let file_lines: Vec<String> = text.lines().map(String::from).collect();
+        let source = preprocess(text, &[]);
+        let ranges = [None, Some((0, 2)), Some((2, 4)), Some((4, 14))];
+        let output: Vec<u8> = vec![0x30, 0x00, 0x30, 0x00, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+        let listing = written("vml-test-listing.lst", |f| write_listing(f, &file_lines, &source, &ranges, &output));
+        assert_eq!(listing, concat!(
+            "; address bytes                     line  source\n",
+            "                                       1  .macro two\n",
+            "                                       2  \tnop\n",
+            "                                       3  \tnop\n",
+            "                                       4  .endm\n",
+            "                                       5  .start:\n",
+            "00000000  30 00                        2+ \tnop\n",
+            "00000002  30 00                        3+ \tnop\n",
+            "00000004  01 02 03 04 05 06 07 08      6  \t.byte 1, 2, 3, 4, 5, 6, 7, 8, 9, 10\n",
+            "0000000c  09 0a\n",
+            "                                       7  ; done\n"
+        ));
+    }
+
+    #[test]
+    fn map_sizes_run_to_the_next_label() {
+        let labels = [(0x10, "main".to_string()), (0, "start".to_string()), (0x10, "main..loop1".to_string()),
+                      (0x18, "end".to_string())];
+        let constants = [("SIZE".to_string(), "0x8".to_string())];
+        let map = written("vml-test-map.map", |f| write_map(f, &labels, &constants, 0x20));
+        assert_eq!(map, concat!(
+            "; address size      label\n",
+            "00000000  00000010  start\n",
+            "00000010  00000008  main\n",
+            "00000010  00000008  main..loop1\n",
+            "00000018  00000008  end\n",
+            "\n",
+            "; constant  value\n",
+            "SIZE = 0x8\n"
+        ));
+    }
+}
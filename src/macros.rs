@@ -0,0 +1,634 @@
+use crate::assembler::{split_operands, evaluate_constant, is_register};
+use crate::errors::*;
+
+use std::collections::HashMap;
+use std::fs;
+use std::process;
+
+// the first pass over assembly, before it is lexed. it handles:
+//   `.macro name a, b` ... `.endm`. `\a` in the body is replaced with the
+//   argument given for `a`, and labels defined in the body get a suffix
+//   unique to each expansion (`.loop` becomes `.loop@3`), so a macro can be
+//   used more than once.
+//   `.include "file.s"`, which is replaced with the lines of the file.
+//   `.if expr`, `.ifdef NAME`, `.else` and `.endif`, which leave out lines
+//   depending on the `.equ` constants (and `-D NAME=VALUE`s) defined so far.
+//   the pseudo-instructions `inc`, `dec`, `clr`, `li`, `bge` and `ble`, which
+//   are replaced with the instructions they stand for (see `pseudo`).
+
+// macros calling macros or files including files deeper than this are
+// assumed to be recursive.
+const MAX_DEPTH: usize = 64;
+
+const PSEUDO_INSTRUCTIONS: [&str; 6] = ["inc", "dec", "clr", "li", "bge", "ble"];
+
+#[derive(Clone)]
+pub struct Expansion {
+    pub line: usize,
+    // what the line is, eg. "in expansion of macro `name`"
+    pub note: String,
+    pub text: String
+}
+
+// a line of assembly once macros are expanded. `line` is where its text was
+// written (0-based), which is inside a macro body or an included file for
+// expanded lines.
+#[derive(Clone)]
+pub struct SourceLine {
+    pub text: String,
+    pub line: usize,
+    // invocations and includes that produced it, outermost first
+    pub expanded_from: Vec<Expansion>
+}
+
+impl SourceLine {
+    // the line the code ends up at in the file, ie. the outermost invocation.
+    pub fn file_line(self: &SourceLine) -> usize {
+        match self.expanded_from.first() {
+            Some(expansion) => expansion.line,
+            None => self.line
+        }
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<(usize, String)>,
+    labels: Vec<String>
+}
+
+// an `.if` or `.ifdef` that has not seen its `.endif` yet.
+struct Condition {
+    origin: SourceLine,
+    // whether the lines are kept
+    active: bool,
+    seen_else: bool
+}
+
+// reports an error at the line `origin` was written on, followed by every
+// invocation it was expanded from, innermost first.
+pub fn format_error_origin(error: String, origin: &SourceLine) {
+    format_errorl(error, origin.line + 1, origin.text.clone());
+    let mut expansions = origin.expanded_from.iter().rev().peekable();
+    while let Some(expansion) = expansions.next() {
+        // a macro invoking itself would repeat the same note many times
+        let mut repeats: usize = 0;
+        while expansions.peek().is_some_and(|e| e.line == expansion.line && e.text == expansion.text) {
+            expansions.next();
+            repeats += 1;
+        }
+        let note = match repeats {
+            0 => expansion.note.clone(),
+            _ => format!("{} ({} more times)", expansion.note, repeats)
+        };
+        format_notel(note, expansion.line + 1, expansion.text.clone());
+    }
+}
+
+// an error and the line it is reported at. the preprocessor passes it up
+// to `preprocess`, which reports it.
+type Failure = (String, SourceLine);
+
+fn failure(error: String, origin: &SourceLine) -> Failure {
+    (error, origin.clone())
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn first_word(text: &str) -> &str {
+    text.split_whitespace().next().unwrap_or("")
+}
+
+// the name of a label defined at the start of `text` (`.name:`), if any.
+fn defined_label(text: &str) -> Option<&str> {
+    let name = text.trim_start().strip_prefix('.')?.split_once(':')?.0;
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        return None;
+    }
+    Some(name)
+}
+
+// the file named by `.include "file"`.
+fn quoted_filename<'a>(operands: &'a str, directive: &str, origin: &SourceLine) -> Result<&'a str, Failure> {
+    let operands = operands.trim();
+    match operands.strip_prefix('"').and_then(|o| o.strip_suffix('"')) {
+        Some(filename) if !filename.is_empty() => Ok(filename),
+        _ => Err(failure(format!("`{}` expects a filename in quotes. (eg. `{} \"file\"`)", directive, directive), origin))
+    }
+}
+
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    constants: HashMap<String, String>,
+    conditions: Vec<Condition>,
+    output: Vec<SourceLine>
+}
+
+pub fn preprocess(file_data: &str, defines: &[(String, String)]) -> Vec<SourceLine> {
+    match expand(file_data, defines) {
+        Ok(output) => output,
+        Err((error, origin)) => {
+            format_error_origin(error, &origin);
+            process::exit(1);
+        }
+    }
+}
+
+fn expand(file_data: &str, defines: &[(String, String)]) -> Result<Vec<SourceLine>, Failure> {
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        expansions: 0,
+        constants: defines.iter().cloned().collect(),
+        conditions: Vec::new(),
+        output: Vec::new()
+    };
+    preprocessor.file(file_data, &[])?;
+    if let Some(condition) = preprocessor.conditions.last() {
+        return Err(failure(format!("`{}` is missing its `.endif`.", first_word(&condition.origin.text)), &condition.origin));
+    }
+    Ok(preprocessor.output)
+}
+
+impl Preprocessor {
+    fn active(self: &Preprocessor) -> bool {
+        self.conditions.iter().all(|c| c.active)
+    }
+
+    // the lines of the file being assembled, or of one it includes.
+    fn file(self: &mut Preprocessor, file_data: &str, expanded_from: &[Expansion]) -> Result<(), Failure> {
+        let lines: Vec<&str> = file_data.lines().collect();
+        let mut index: usize = 0;
+
+        while index < lines.len() {
+            let origin = SourceLine { text: lines[index].to_string(), line: index, expanded_from: expanded_from.to_vec() };
+            match first_word(lines[index]) {
+                ".macro" if self.active() => {
+                    let mut words = lines[index].split_whitespace().skip(1);
+                    let name = match words.next() {
+                        Some(name) if is_identifier(name) => name.to_string(),
+                        _ => return Err(failure("`.macro` without a name. (macros are defined with `.macro <name> <args...>`)".to_string(), &origin))
+                    };
+                    if self.macros.contains_key(&name) {
+                        return Err(failure(format!("Repeated definition of macro `{}`.", name), &origin));
+                    }
+                    let mut params: Vec<String> = Vec::new();
+                    for param in words.collect::<Vec<&str>>().join(" ").split([',', ' ']) {
+                        if param.is_empty() { continue; }
+                        if !is_identifier(param) || params.iter().any(|p| p == param) {
+                            return Err(failure(format!("Bad parameter `{}` for macro `{}`.", param, name), &origin));
+                        }
+                        params.push(param.to_string());
+                    }
+
+                    let mut body: Vec<(usize, String)> = Vec::new();
+                    index += 1;
+                    loop {
+                        if index >= lines.len() {
+                            return Err(failure(format!("Macro `{}` is missing its `.endm`.", name), &origin));
+                        }
+                        match first_word(lines[index]) {
+                            ".endm" => break,
+                            ".macro" => return Err(failure("Macros cannot be defined inside a macro.".to_string(),
+                                                           &SourceLine { text: lines[index].to_string(), line: index, expanded_from: expanded_from.to_vec() })),
+                            _ => body.push((index, lines[index].to_string()))
+                        }
+                        index += 1;
+                    }
+                    let labels: Vec<String> = body.iter()
+                        .filter_map(|(_, text)| defined_label(text).map(String::from))
+                        .collect();
+                    self.macros.insert(name, Macro { params, body, labels });
+                },
+                ".endm" if self.active() => return Err(failure("`.endm` without a `.macro`.".to_string(), &origin)),
+                _ => self.line(origin)?
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn line(self: &mut Preprocessor, origin: SourceLine) -> Result<(), Failure> {
+        if self.conditional(&origin)? || !self.active() {
+            return Ok(());
+        }
+        // a label can come before the invocation on the same line
+        let mut rest = origin.text.trim_start();
+        let label = defined_label(rest);
+        if let Some(label) = label {
+            rest = rest[label.len() + 2..].trim_start();
+        }
+        let name = first_word(rest);
+        if origin.expanded_from.len() >= MAX_DEPTH && (name == ".include" || self.macros.contains_key(name)) {
+            return Err(failure(format!("`{}` is expanded too deeply. (does it use itself?)", name), &origin));
+        }
+
+        if name == ".equ" {
+            // kept to decide `.if`s, and lexed like any other directive
+            let definition = split_operands(&rest[name.len()..]).join(", ");
+            if let Some((constant, value)) = definition.split_once(|c: char| c.is_whitespace()) {
+                self.constants.insert(constant.to_string(), value.trim().to_string());
+            }
+            self.output.push(origin);
+            return Ok(());
+        }
+        if name != ".include" && !self.macros.contains_key(name) && !PSEUDO_INSTRUCTIONS.contains(&name) {
+            self.output.push(origin);
+            return Ok(());
+        }
+        if let Some(label) = label {
+            self.output.push(SourceLine {
+                text: format!(".{}:", label),
+                line: origin.line,
+                expanded_from: origin.expanded_from.clone()
+            });
+        }
+
+        if !self.macros.contains_key(name) && PSEUDO_INSTRUCTIONS.contains(&name) {
+            let mut expanded_from = origin.expanded_from.clone();
+            expanded_from.push(Expansion { line: origin.line, note: format!("in expansion of pseudo-instruction `{}`", name), text: origin.text.clone() });
+            for text in self.pseudo(name, &rest[name.len()..], &origin)? {
+                self.output.push(SourceLine { text, line: origin.line, expanded_from: expanded_from.clone() });
+            }
+            return Ok(());
+        }
+        if name == ".include" {
+            let filename = quoted_filename(&rest[name.len()..], name, &origin)?;
+            let contents = match fs::read_to_string(filename) {
+                Ok(contents) => contents,
+                Err(why) => return Err(failure(format!("Couldn't read `{}`: {}", filename, why), &origin))
+            };
+            let mut expanded_from = origin.expanded_from.clone();
+            expanded_from.push(Expansion { line: origin.line, note: format!("in `{}`, included here", filename), text: origin.text.clone() });
+            return self.file(&contents, &expanded_from);
+        }
+
+        let definition = &self.macros[name];
+        let args = split_operands(&rest[name.len()..]);
+        if args.len() != definition.params.len() || args.iter().any(|a| a.is_empty()) {
+            return Err(failure(format!("Macro `{}` takes {} argument(s), found {}.", name, definition.params.len(), args.len()), &origin));
+        }
+
+        self.expansions += 1;
+        let id = self.expansions;
+        let lines: Vec<SourceLine> = definition.body.iter()
+            .map(|(line, text)| {
+                let mut expanded_from = origin.expanded_from.clone();
+                expanded_from.push(Expansion { line: origin.line, note: format!("in expansion of macro `{}`", name), text: origin.text.clone() });
+                let mut expanded = SourceLine { text: text.clone(), line: *line, expanded_from };
+                expanded.text = substitute(&expanded, definition, &args, id)?;
+                Ok(expanded)
+            })
+            .collect::<Result<_, Failure>>()?;
+        for expanded in lines {
+            self.line(expanded)?;
+        }
+        Ok(())
+    }
+
+    // the instructions a pseudo-instruction stands for. r15 is left to them
+    // as a scratch register.
+    //     inc rX      adr r15, 1 / iadd rX, r15
+    //     dec rX      adr r15, 1 / isub rX, r15
+    //     clr rX      isub rX, rX
+    //     li rX, v    adr rX, v when v is known to fit in 32 bits or is a
+    //                 label, mov rX, v otherwise
+    //     bge target  bgt target / beq target
+    //     ble target  blt target / beq target
+    fn pseudo(self: &Preprocessor, name: &str, operands: &str, origin: &SourceLine) -> Result<Vec<String>, Failure> {
+        let operands = split_operands(operands);
+        let register = |operand: &str| {
+            if !is_register(operand) {
+                return Err(failure(format!("`{}` expects a register, found `{}`.", name, operand), origin));
+            }
+            if !operand[1..].parse::<u8>().is_ok_and(|n| n < 16) {
+                return Err(failure(format!("`{}` is not a register. (there are 16, r0 to r15)", operand), origin));
+            }
+            if operand == "r15" && (name == "inc" || name == "dec") {
+                return Err(failure(format!("`{}` uses r15 as its scratch register, so it cannot {} r15 itself.", name, if name == "inc" { "increment" } else { "decrement" }), origin));
+            }
+            Ok(())
+        };
+        let expected = if name == "li" { 2 } else { 1 };
+        if operands.len() != expected || operands.iter().any(|o| o.is_empty()) {
+            let example = match name {
+                "li" => "r1, 42",
+                "bge" | "ble" => ".loop",
+                _ => "r1"
+            };
+            return Err(failure(format!("`{}` takes {} operand(s), found {}. (eg. `{} {}`)", name, expected, operands.len(), name, example), origin));
+        }
+
+        let operand = &operands[0];
+        Ok(match name {
+            "inc" | "dec" => {
+                register(operand)?;
+                let op = if name == "inc" { "iadd" } else { "isub" };
+                vec![String::from("\t\tadr \tr15, 1"), format!("\t\t{}\t{}, r15", op, operand)]
+            },
+            "clr" => {
+                register(operand)?;
+                vec![format!("\t\tisub\t{}, {}", operand, operand)]
+            },
+            "li" => {
+                register(operand)?;
+                let value = operands[1].strip_prefix("U$").or(operands[1].strip_prefix('$')).unwrap_or(&operands[1]);
+                // labels are 32-bit addresses. anything not known yet could be
+                // wider, so it gets the 64-bit `mov`.
+                let short = match evaluate_constant(value, &self.constants) {
+                    Ok(value) => value <= u32::MAX as u64,
+                    Err(_) => value.starts_with('.') && !value.contains(|c: char| c.is_whitespace() || "|&<>+*/()".contains(c))
+                };
+                let op = if short { "adr " } else { "mov " };
+                vec![format!("\t\t{}\t{}, {}", op, operand, value)]
+            },
+            _ => {
+                let branch = if name == "bge" { "bgt " } else { "blt " };
+                vec![format!("\t\t{}\t{}", branch, operand), format!("\t\tbeq \t{}", operand)]
+            }
+        })
+    }
+
+    // handles `.if`, `.ifdef`, `.else` and `.endif`, returning false for
+    // any other line.
+    fn conditional(self: &mut Preprocessor, origin: &SourceLine) -> Result<bool, Failure> {
+        let text = origin.text.split(';').next().unwrap_or("").trim();
+        let directive = first_word(text);
+        let operand = text[directive.len()..].trim();
+        match directive {
+            ".if" | ".ifdef" => {
+                // lines inside a left out block are not looked at, but their
+                // conditions still need to pair up with an `.endif`
+                let active = self.active() && if directive == ".ifdef" {
+                    if !is_identifier(operand) {
+                        return Err(failure("`.ifdef` expects the name of a constant.".to_string(), origin));
+                    }
+                    self.constants.contains_key(operand)
+                } else {
+                    if operand.is_empty() {
+                        return Err(failure("`.if` expects an expression.".to_string(), origin));
+                    }
+                    match evaluate_constant(operand, &self.constants) {
+                        Ok(value) => value != 0,
+                        Err(error) => return Err(failure(error, origin))
+                    }
+                };
+                self.conditions.push(Condition { origin: origin.clone(), active, seen_else: false });
+            },
+            ".else" => {
+                let outer = self.conditions.len() < 2 || self.conditions[..self.conditions.len() - 1].iter().all(|c| c.active);
+                match self.conditions.last_mut() {
+                    Some(condition) if !condition.seen_else => {
+                        condition.active = outer && !condition.active;
+                        condition.seen_else = true;
+                    },
+                    Some(_) => return Err(failure("`.else` after another `.else`.".to_string(), origin)),
+                    None => return Err(failure("`.else` without an `.if`.".to_string(), origin))
+                }
+            },
+            ".endif" => {
+                if self.conditions.pop().is_none() {
+                    return Err(failure("`.endif` without an `.if`.".to_string(), origin));
+                }
+            },
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+}
+
+// replaces `\param` with its argument and `.label` with `.label@id` for
+// labels the body defines, leaving strings and comments alone.
+fn substitute(origin: &SourceLine, definition: &Macro, args: &[String], id: usize) -> Result<String, Failure> {
+    let chars: Vec<char> = origin.text.chars().collect();
+    let mut output: String = String::new();
+    let mut quote: Option<char> = None;
+    let mut index: usize = 0;
+    let word_end = |start: usize| -> usize {
+        let mut end = start;
+        while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') { end += 1; }
+        end
+    };
+
+    while index < chars.len() {
+        let c = chars[index];
+        if let Some(q) = quote {
+            output.push(c);
+            if c == '\\' && index + 1 < chars.len() {
+                output.push(chars[index + 1]);
+                index += 1;
+            } else if c == q {
+                quote = None;
+            }
+            index += 1;
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                output.push(c);
+            },
+            ';' => {
+                output.extend(&chars[index..]);
+                break;
+            },
+            '\\' => {
+                let end = word_end(index + 1);
+                let param: String = chars[index + 1..end].iter().collect();
+                match definition.params.iter().position(|p| *p == param) {
+                    Some(position) => output += &args[position],
+                    None => return Err(failure(format!("`\\{}` is not a parameter of this macro.", param), origin))
+                }
+                index = end;
+                continue;
+            },
+            '.' => {
+                // labels can hold anything up to the characters that end them
+                let mut end = index + 1;
+                while end < chars.len() && !matches!(chars[end], ':' | ' ' | '\t' | ',' | ';') { end += 1; }
+                let label: String = chars[index + 1..end].iter().collect();
+                output.push('.');
+                output += &label;
+                if definition.labels.contains(&label) {
+                    output += &format!("@{}", id);
+                }
+                index = end;
+                continue;
+            },
+            _ => output.push(c)
+        }
+        index += 1;
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn expanded(source: &str, defines: &[(&str, &str)]) -> Vec<String> {
+        let defines: Vec<(String, String)> = defines.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        match expand(source, &defines) {
+            Ok(output) => output.into_iter().map(|line| line.text.trim().to_string()).collect(),
+            Err((error, _)) => panic!("{}", error)
+        }
+    }
+
+    fn error(source: &str) -> String {
+        expand(source, &[]).err().expect("expected an error").0
+    }
+
+    #[test]
+    fn parameters_are_substituted() {
+        let source = ".macro move dst, src\n\tmov \\dst, \\src ; \\src\n\t.ascii \"\\dst\"\n.endm\n\tmove r1, $8\n\tmove r2, r1\n";
+        assert_eq!(expanded(source, &[]), [
+            "mov r1, $8 ; \\src", ".ascii \"\\dst\"",
+            "mov r2, r1 ; \\src", ".ascii \"\\dst\""
+        ]);
+    }
+
+    #[test]
+    fn labels_get_a_suffix_per_expansion() {
+        let source = ".macro spin\n.loop:\tjmp .loop\n\tjmp .out\n.endm\n.top: spin\n\tspin\n";
+        assert_eq!(expanded(source, &[]), [
+            ".top:", ".loop@1:\tjmp .loop@1", "jmp .out",
+            ".loop@2:\tjmp .loop@2", "jmp .out"
+        ]);
+    }
+
+    #[test]
+    fn includes_nest_but_do_not_recurse() {
+        let dir = env::temp_dir();
+        let outer = dir.join("vml-test-include-outer.s").to_string_lossy().to_string();
+        let inner = dir.join("vml-test-include-inner.s").to_string_lossy().to_string();
+        let looped = dir.join("vml-test-include-loop.s").to_string_lossy().to_string();
+        fs::write(&outer, format!("\tnop\n\t.include \"{}\"\n", inner)).unwrap();
+        fs::write(&inner, "\tret\n").unwrap();
+        fs::write(&looped, format!("\t.include \"{}\"\n", looped)).unwrap();
+
+        let output = expand(&format!("\t.include \"{}\"\n\thlt\n", outer), &[]).ok().unwrap();
+        let texts: Vec<&str> = output.iter().map(|line| line.text.trim()).collect();
+        assert_eq!(texts, ["nop", "ret", "hlt"]);
+        // the inner line remembers both includes, and ends up on line 1
+        assert_eq!(output[1].expanded_from.len(), 2);
+        assert_eq!((output[1].line, output[1].file_line()), (0, 0));
+        assert_eq!(output[2].file_line(), 1);
+
+        assert_eq!(error(&format!("\t.include \"{}\"\n", looped)), "`.include` is expanded too deeply. (does it use itself?)");
+        for file in [&outer, &inner, &looped] {
+            fs::remove_file(file).unwrap();
+        }
+        assert!(error("\t.include \"vml-test-missing.s\"\n").starts_with("Couldn't read `vml-test-missing.s`"));
+        assert_eq!(error("\t.include file.s\n"), "`.include` expects a filename in quotes. (eg. `.include \"file\"`)");
+    }
+
+    #[test]
+    fn recursive_macros_are_stopped() {
+        assert_eq!(error(".macro again\n\tagain\n.endm\n\tagain\n"), "`again` is expanded too deeply. (does it use itself?)");
+    }
+
+    #[test]
+    fn conditions_nest() {
+        let source = "\
+.ifdef DEBUG
+\tone
+.if LEVEL - 1
+\ttwo
+.else
+\tthree
+.endif
+.else
+\tfour
+.if 1
+\tfive
+.endif
+.endif
+.equ LATE 1
+.ifdef LATE
+\tsix
+.endif
+";
+        assert_eq!(expanded(source, &[("DEBUG", "1"), ("LEVEL", "2")]), ["one", "two", ".equ LATE 1", "six"]);
+        assert_eq!(expanded(source, &[("DEBUG", "1"), ("LEVEL", "1")]), ["one", "three", ".equ LATE 1", "six"]);
+        assert_eq!(expanded(source, &[]), ["four", "five", ".equ LATE 1", "six"]);
+        // a left out block is not expanded, so its macros are never defined
+        assert_eq!(expanded(".if 0\n.macro m\n.endm\n.endif\n\tm\n", &[]), ["m"]);
+    }
+
+    #[test]
+    fn condition_errors() {
+        assert_eq!(error(".if 1\n"), "`.if` is missing its `.endif`.");
+        assert_eq!(error(".ifdef A\n.else\n.else\n.endif\n"), "`.else` after another `.else`.");
+        assert_eq!(error(".else\n"), "`.else` without an `.if`.");
+        assert_eq!(error(".endif\n"), "`.endif` without an `.if`.");
+        assert_eq!(error(".ifdef 1 + 1\n.endif\n"), "`.ifdef` expects the name of a constant.");
+        assert_eq!(error(".if\n.endif\n"), "`.if` expects an expression.");
+        assert!(!error(".if UNDEFINED\n.endif\n").is_empty());
+    }
+
+    #[test]
+    fn macro_errors() {
+        assert_eq!(error(".macro\n.endm\n"), "`.macro` without a name. (macros are defined with `.macro <name> <args...>`)");
+        assert_eq!(error(".macro m\n.endm\n.macro m\n.endm\n"), "Repeated definition of macro `m`.");
+        assert_eq!(error(".macro m a, a\n.endm\n"), "Bad parameter `a` for macro `m`.");
+        assert_eq!(error(".macro m a-b\n.endm\n"), "Bad parameter `a-b` for macro `m`.");
+        assert_eq!(error(".macro m\n\tnop\n"), "Macro `m` is missing its `.endm`.");
+        assert_eq!(error(".macro m\n.macro n\n.endm\n"), "Macros cannot be defined inside a macro.");
+        assert_eq!(error(".endm\n"), "`.endm` without a `.macro`.");
+        assert_eq!(error(".macro m a\n.endm\n\tm\n"), "Macro `m` takes 1 argument(s), found 0.");
+        assert_eq!(error(".macro m a\n.endm\n\tm r1, r2\n"), "Macro `m` takes 1 argument(s), found 2.");
+        assert_eq!(error(".macro m a\n\tpush \\b\n.endm\n\tm r1\n"), "`\\b` is not a parameter of this macro.");
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let source = "\
+.equ BIG 0x100000000
+\tinc r1
+\tdec r14
+\tclr r0
+\tli r2, 42
+\tli r3, $0xFFFFFFFF
+\tli r4, 0x100000000
+\tli r5, BIG
+\tli r6, .table
+\tli r7, .table + 8
+\tli r8, LATER
+.next:\tbge .loop
+\tble .loop
+";
+        assert_eq!(expanded(source, &[]), [
+            ".equ BIG 0x100000000",
+            "adr \tr15, 1", "iadd\tr1, r15",
+            "adr \tr15, 1", "isub\tr14, r15",
+            "isub\tr0, r0",
+            "adr \tr2, 42",
+            "adr \tr3, 0xFFFFFFFF",
+            "mov \tr4, 0x100000000",
+            "mov \tr5, BIG",
+            "adr \tr6, .table",
+            "mov \tr7, .table + 8",
+            "mov \tr8, LATER",
+            ".next:", "bgt \t.loop", "beq \t.loop",
+            "blt \t.loop", "beq \t.loop"
+        ]);
+        // a macro of the same name wins
+        assert_eq!(expanded(".macro clr a\n\tmov \\a, $0\n.endm\n\tclr r1\n", &[]), ["mov r1, $0"]);
+    }
+
+    #[test]
+    fn pseudo_instruction_errors() {
+        assert_eq!(error("\tinc 5\n"), "`inc` expects a register, found `5`.");
+        assert_eq!(error("\tli .x, 5\n"), "`li` expects a register, found `.x`.");
+        assert_eq!(error("\tclr r16\n"), "`r16` is not a register. (there are 16, r0 to r15)");
+        assert_eq!(error("\tinc r15\n"), "`inc` uses r15 as its scratch register, so it cannot increment r15 itself.");
+        assert_eq!(error("\tdec r15\n"), "`dec` uses r15 as its scratch register, so it cannot decrement r15 itself.");
+        assert_eq!(error("\tclr r1, r2\n"), "`clr` takes 1 operand(s), found 2. (eg. `clr r1`)");
+        assert_eq!(error("\tli r1\n"), "`li` takes 2 operand(s), found 1. (eg. `li r1, 42`)");
+        assert_eq!(error("\tli r1,\n"), "`li` takes 2 operand(s), found 2. (eg. `li r1, 42`)");
+        assert_eq!(error("\tbge\n"), "`bge` takes 1 operand(s), found 0. (eg. `bge .loop`)");
+    }
+}
@@ -3,6 +3,7 @@ use std::process;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
 
 pub mod errors;
 pub mod vml_cpu;
@@ -10,8 +11,31 @@ pub mod assembler;
 pub mod token;
 pub mod variable;
 pub mod util;
+pub mod debug_info;
+pub mod heap;
+pub mod handles;
+pub mod rng;
+pub mod policy;
+pub mod scheduler;
+pub mod shared;
+pub mod exceptions;
+pub mod interrupts;
+pub mod devices;
+pub mod image;
+pub mod wav;
+pub mod macros;
+pub mod expression;
+pub mod listing;
+pub mod linker;
+pub mod archive;
 
 use crate::assembler::*;
+use crate::debug_info::*;
+use crate::policy::*;
+use crate::devices::*;
+use crate::linker::*;
+use crate::archive::*;
+use crate::exceptions::*;
 
 static VERSION: &str = "0.0.0a *ALPHA BUILD*";
 
@@ -20,6 +44,8 @@ enum RunType {
     COMPILE,
     RUN,
     ASSEMBLE,
+    LINK,
+    ARCHIVE,
     NONE
 }
 
@@ -32,6 +58,15 @@ fn load_binary_file(filename: &String) -> Vec<u8> {
     return buff;
 }
 
+// parses a framebuffer size such as `320x200`.
+fn parse_dimensions(value: &str) -> Option<(usize, usize)> {
+    let (width, height) = value.split_once('x')?;
+    let width = width.parse::<usize>().ok()?;
+    let height = height.parse::<usize>().ok()?;
+    if width == 0 || height == 0 || width > 4096 || height > 4096 { return None; }
+    Some((width, height))
+}
+
 fn load_text_file(filename: &String) -> String {
     let contents = fs::read_to_string(filename).expect(&*format!("Unable to read the file '{}'.", filename)); 
     return contents;
@@ -41,6 +76,22 @@ fn main() {
     let mut args: Vec<String> = env::args().collect();
     let mut runtype: RunType = RunType::NONE;
     let mut filename: String = String::new();
+    let mut sanitize: bool = false;
+    let mut leaks: bool = false;
+    let mut program_args: Vec<String> = Vec::new();
+    let mut seed: Option<u64> = None;
+    let mut policy: Policy = Policy::new();
+    let mut framebuffer: Option<(usize, usize)> = None;
+    let mut framebuffer_out: String = String::from("framebuffer.png");
+    let mut audio: Option<u32> = None;
+    let mut audio_out: String = String::from("audio.wav");
+    let mut listing: Option<String> = None;
+    let mut map: Option<String> = None;
+    let mut object: bool = false;
+    let mut defines: Vec<(String, String)> = Vec::new();
+    let mut output: Option<String> = None;
+    // the objects given to `link` and `ar`
+    let mut inputs: Vec<String> = Vec::new();
 
     if args.len() == 1 {
         eprintln!("{}", errors::err_no_args());
@@ -48,26 +99,137 @@ fn main() {
     }
     
     args.remove(0);
-    for i in args {
-        if runtype == RunType::NONE {
+    let mut args_iter = args.into_iter();
+    while let Some(i) = args_iter.next() {
+        if i == "--" {
+            // everything after `--` is passed on to the VML program
+            program_args.extend(args_iter.by_ref());
+        } else if i == "--sanitize" {
+            sanitize = true;
+        } else if i == "--leaks" {
+            leaks = true;
+        } else if i == "--allow-net" {
+            policy.allow_net = true;
+        } else if i == "--allow-exec" {
+            policy.allow_exec = true;
+        } else if i == "--seed" {
+            match args_iter.next().and_then(|value| value.parse::<u64>().ok()) {
+                Some(value) => seed = Some(value),
+                None => {
+                    eprintln!("{}", errors::err_bad_seed());
+                    process::exit(1);
+                }
+            }
+        } else if i == "--framebuffer" {
+            match args_iter.next().and_then(|value| parse_dimensions(&value)) {
+                Some(size) => framebuffer = Some(size),
+                None => {
+                    eprintln!("{}", errors::err_bad_framebuffer());
+                    process::exit(1);
+                }
+            }
+        } else if i == "--framebuffer-out" {
+            match args_iter.next() {
+                Some(value) => framebuffer_out = value,
+                None => {
+                    eprintln!("{}", errors::err_bad_framebuffer());
+                    process::exit(1);
+                }
+            }
+        } else if i == "--audio" {
+            match args_iter.next().and_then(|value| value.parse::<u32>().ok()).filter(|rate| *rate > 0 && *rate <= u32::MAX / 2) {
+                Some(rate) => audio = Some(rate),
+                None => {
+                    eprintln!("{}", errors::err_bad_audio());
+                    process::exit(1);
+                }
+            }
+        } else if i == "--audio-out" {
+            match args_iter.next() {
+                Some(value) => audio_out = value,
+                None => {
+                    eprintln!("{}", errors::err_bad_audio());
+                    process::exit(1);
+                }
+            }
+        } else if i == "--listing" || i == "--map" {
+            match args_iter.next() {
+                Some(value) if i == "--listing" => listing = Some(value),
+                Some(value) => map = Some(value),
+                None => {
+                    eprintln!("{}", errors::err_bad_listing());
+                    process::exit(1);
+                }
+            }
+        } else if i == "-D" {
+            // `-D NAME=VALUE`, or `-D NAME` for 1
+            match args_iter.next() {
+                Some(value) if !value.is_empty() && !value.starts_with('=') => {
+                    let (name, value) = value.split_once('=').unwrap_or((&value, "1"));
+                    defines.push((name.to_string(), value.to_string()));
+                },
+                _ => {
+                    eprintln!("{}", errors::err_bad_define());
+                    process::exit(1);
+                }
+            }
+        } else if i == "--obj" {
+            object = true;
+        } else if i == "-o" {
+            match args_iter.next() {
+                Some(value) => output = Some(value),
+                None => {
+                    eprintln!("{}", errors::err_bad_output());
+                    process::exit(1);
+                }
+            }
+        } else if runtype == RunType::NONE {
             match &*i {
                 "-c" => runtype = RunType::COMPILE,
                 "-r" => runtype = RunType::RUN,
                 "-a" => runtype = RunType:: ASSEMBLE,
+                "link" => runtype = RunType::LINK,
+                "ar" => runtype = RunType::ARCHIVE,
                 _ => {
                     eprintln!("{}", errors::err_arg_not_found());
                     process::exit(1);
                 },
             }
+        } else if runtype == RunType::LINK || runtype == RunType::ARCHIVE {
+            inputs.push(i);
         } else {
             filename = i;
         }
     }
 
+    // only hand written assembly can test constants with `.if`/`.ifdef`
+    if !defines.is_empty() && runtype != RunType::ASSEMBLE {
+        eprintln!("{}", errors::err_bad_define());
+        process::exit(1);
+    }
+
+    // objects are named after their source unless `-o` says otherwise
+    let output: String = match output {
+        Some(output) => output,
+        None if object && runtype != RunType::LINK => Path::new(&filename).with_extension("o").to_string_lossy().to_string(),
+        None if runtype == RunType::ARCHIVE => String::from("out.a"),
+        None => String::from("out.bin")
+    };
+
     match &runtype {
         RunType::COMPILE => { 
             let contents: String = load_text_file(&filename);
             let mut lexer: Lexer = Lexer::new();
+            if let Some(filename) = listing {
+                lexer.set_listing(filename);
+            }
+            if let Some(filename) = map {
+                lexer.set_map(filename);
+            }
+            if object {
+                lexer.set_object();
+            }
+            lexer.set_output(output.clone());
             lexer.lex_vml(contents);
             let assembly: String = lexer.tokens_to_assembly();
             lexer.lex_asm(assembly);
@@ -77,6 +239,29 @@ fn main() {
         RunType::RUN => {
             let file_data: Vec<u8> = load_binary_file(&filename);
             let mut vm_cpu: vml_cpu::VMLCpu = vml_cpu::VMLCpu::new();
+            if let Some(info) = DebugInfo::load(&Path::new(&filename).with_extension("sym").to_string_lossy()) {
+                vm_cpu.set_debug_info(info);
+            }
+            if sanitize {
+                vm_cpu.enable_sanitizer();
+            }
+            if leaks {
+                vm_cpu.enable_leak_report();
+            }
+            if let Some(value) = seed {
+                vm_cpu.set_seed(value);
+            }
+            vm_cpu.set_policy(policy);
+            vm_cpu.map_device(CONSOLE_BASE, Box::new(Console));
+            if let Some((width, height)) = framebuffer {
+                vm_cpu.map_device(FRAMEBUFFER_BASE, Box::new(Framebuffer::new(width, height, framebuffer_out)));
+            }
+            if let Some(rate) = audio {
+                vm_cpu.map_device(AUDIO_BASE, Box::new(Audio::new(rate, audio_out)));
+            }
+            program_args.insert(0, filename.clone());
+            vm_cpu.set_args(program_args);
+            install_panic_hook();
             vm_cpu.exec(&file_data, &file_data.len());
         },
         RunType::ASSEMBLE => {
@@ -85,10 +270,44 @@ fn main() {
             let contents: String = load_text_file(&filename);
 
             let mut lexer: Lexer = Lexer::new();
+            if let Some(filename) = listing {
+                lexer.set_listing(filename);
+            }
+            if let Some(filename) = map {
+                lexer.set_map(filename);
+            }
+            if object {
+                lexer.set_object();
+            }
+            lexer.set_output(output.clone());
+            for (name, value) in defines {
+                lexer.define(name, value);
+            }
             lexer.lex_asm(contents);
             let filesize: usize = lexer.assemble_asm();
             println!("Finished compilation: {:.2}KB (ALL OK).", (filesize as f64) / 1024.0);
         },
+        RunType::LINK => {
+            if inputs.is_empty() {
+                eprintln!("{}", errors::err_no_objects());
+                process::exit(1);
+            }
+            let libraries: Vec<Library> = inputs.iter().map(|filename| Library::load(filename)).collect();
+            let (binary, debug_info) = link(&select(&libraries));
+            write_binary(&output, &binary, &debug_info);
+            println!("Finished linking: {:.2}KB (ALL OK).", (binary.len() as f64) / 1024.0);
+        },
+        RunType::ARCHIVE => {
+            if inputs.is_empty() {
+                eprintln!("{}", errors::err_no_objects());
+                process::exit(1);
+            }
+            let archive: Archive = archive(&output, &inputs);
+            if let Err(why) = fs::write(&output, archive.to_text()) {
+                panic!("Couldn't write to file {}: {}", output, why);
+            }
+            println!("Finished archiving: {} object(s), {} symbol(s) (ALL OK).", archive.members.len(), archive.index.len());
+        },
         _ => {},
     }
 }
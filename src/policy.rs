@@ -0,0 +1,33 @@
+use std::net::SocketAddr;
+
+// what a running program is allowed to do on the host. everything outside
+// the VM's own memory and files that the user named goes through here.
+
+#[derive(Clone)]
+pub struct Policy {
+    // connections to hosts other than the local machine
+    pub allow_net: bool,
+    // running host commands
+    pub allow_exec: bool
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Policy {
+            allow_net: false,
+            allow_exec: false
+        }
+    }
+
+    // loopback is always allowed so that programs can talk to each other on
+    // the same machine without any flags.
+    pub fn allows_address(self: &Policy, addr: &SocketAddr) -> bool {
+        self.allow_net || addr.ip().is_loopback()
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
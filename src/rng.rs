@@ -0,0 +1,31 @@
+// xorshift64* generator backing the random number syscalls. it is small,
+// fast and, given the same seed, produces the same sequence on every host.
+
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Rng { state: 0 };
+        rng.seed(seed);
+        rng
+    }
+
+    pub fn seed(self: &mut Rng, seed: u64) {
+        // xorshift gets stuck on a zero state, so scramble the seed with a
+        // splitmix64 step first.
+        let mut z = seed.wrapping_add(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^= z >> 31;
+        self.state = if z == 0 { 0x9E3779B97F4A7C15 } else { z };
+    }
+
+    pub fn next_u64(self: &mut Rng) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::exceptions::*;
+use crate::interrupts::*;
+
+// cooperative tasks for the VM. every task has its own pc, registers, data
+// stack and return stack, and all of them share the VM's memory. the task
+// that is running lives directly in `VMLCpu`; the others wait here until a
+// `yield` or a blocking syscall hands control over round-robin.
+
+pub enum Wait {
+    Nothing,
+    Join(u64),
+    // for a value to arrive on (or for the closing of) a channel
+    Recv(u64),
+    // for room in a full channel
+    Send(u64)
+}
+
+// a FIFO of u64 values passed between tasks. a capacity of 0 means the
+// channel never fills up.
+pub struct Channel {
+    pub queue: VecDeque<u64>,
+    pub capacity: usize,
+    pub closed: bool
+}
+
+impl Channel {
+    pub fn is_full(self: &Channel) -> bool {
+        self.capacity != 0 && self.queue.len() >= self.capacity
+    }
+}
+
+pub struct Task {
+    pub id: u64,
+    pub pc: usize,
+    pub registers: Vec<u64>,
+    pub stack: Vec<u64>,
+    pub return_stack: Vec<usize>,
+    pub handlers: Vec<Handler>,
+    pub interrupts: Vec<InterruptFrame>,
+    pub flags: u8,
+    pub wait: Wait
+}
+
+// why the running task is giving up the VM after the current instruction.
+pub enum Switch {
+    // move on to the next instruction when the task is resumed
+    Yield,
+    // run the same instruction again when the task is resumed
+    Block(Wait),
+    // the task has returned from its entry method
+    Exit
+}
+
+pub struct Scheduler {
+    pub current: u64,
+    waiting: VecDeque<Task>,
+    // the value left on top of the stack by every task that has finished
+    finished: HashMap<u64, u64>,
+    next_id: u64,
+    pub channels: HashMap<u64, Channel>,
+    next_channel: u64
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        // task 0 runs `main`; when it returns the program ends.
+        Scheduler {
+            current: 0,
+            waiting: VecDeque::new(),
+            finished: HashMap::new(),
+            next_id: 1,
+            channels: HashMap::new(),
+            next_channel: 1
+        }
+    }
+
+    pub fn spawn(self: &mut Scheduler, pc: usize, stack: Vec<u64>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.waiting.push_back(Task {
+            id,
+            pc,
+            registers: vec![0; 16],
+            stack,
+            return_stack: Vec::new(),
+            handlers: Vec::new(),
+            interrupts: Vec::new(),
+            flags: 0,
+            wait: Wait::Nothing
+        });
+        id
+    }
+
+    pub fn create_channel(self: &mut Scheduler, capacity: usize) -> u64 {
+        let id = self.next_channel;
+        self.next_channel += 1;
+        self.channels.insert(id, Channel { queue: VecDeque::new(), capacity, closed: false });
+        id
+    }
+
+    pub fn result_of(self: &Scheduler, id: u64) -> Option<u64> {
+        self.finished.get(&id).copied()
+    }
+
+    pub fn exists(self: &Scheduler, id: u64) -> bool {
+        id == self.current || self.finished.contains_key(&id) || self.waiting.iter().any(|t| t.id == id)
+    }
+
+    pub fn finish(self: &mut Scheduler, id: u64, result: u64) {
+        self.finished.insert(id, result);
+    }
+
+    pub fn park(self: &mut Scheduler, task: Task) {
+        self.waiting.push_back(task);
+    }
+
+    fn runnable(self: &Scheduler, task: &Task) -> bool {
+        match task.wait {
+            Wait::Nothing => true,
+            Wait::Join(id) => self.finished.contains_key(&id),
+            Wait::Recv(id) => self.channels.get(&id).is_none_or(|c| c.closed || !c.queue.is_empty()),
+            Wait::Send(id) => self.channels.get(&id).is_none_or(|c| c.closed || !c.is_full())
+        }
+    }
+
+    // takes the next task that is able to run, in round-robin order. returns
+    // None when every task is blocked.
+    pub fn next_runnable(self: &mut Scheduler) -> Option<Task> {
+        for _ in 0..self.waiting.len() {
+            let task = self.waiting.pop_front()?;
+            if self.runnable(&task) {
+                self.current = task.id;
+                return Some(task);
+            }
+            self.waiting.push_back(task);
+        }
+        None
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // takes the next task and parks it again waiting on `wait`.
+    fn block(scheduler: &mut Scheduler, wait: Wait) -> u64 {
+        let mut task = scheduler.next_runnable().unwrap();
+        task.wait = wait;
+        let id = task.id;
+        scheduler.park(task);
+        id
+    }
+
+    // a task that has not run yet.
+    fn task(id: u64) -> Task {
+        Task {
+            id,
+            pc: 0,
+            registers: vec![0; 16],
+            stack: Vec::new(),
+            return_stack: Vec::new(),
+            handlers: Vec::new(),
+            interrupts: Vec::new(),
+            flags: 0,
+            wait: Wait::Nothing
+        }
+    }
+
+    #[test]
+    fn runnable_tasks_are_taken_round_robin() {
+        let mut scheduler = Scheduler::new();
+        let a = scheduler.spawn(0x10, vec![1]);
+        let b = scheduler.spawn(0x20, vec![2]);
+        let c = scheduler.spawn(0x30, vec![3]);
+        let channel = scheduler.create_channel(0);
+
+        assert_eq!(block(&mut scheduler, Wait::Recv(channel)), a);
+        let task = scheduler.next_runnable().unwrap();
+        assert_eq!((task.id, task.pc, task.stack.clone()), (b, 0x20, vec![2]));
+        assert_eq!(scheduler.current, b);
+        scheduler.park(task);
+
+        // `a` is skipped over while its channel is empty
+        assert_eq!(scheduler.next_runnable().map(|t| t.id), Some(c));
+        assert_eq!(scheduler.next_runnable().map(|t| t.id), Some(b));
+        assert!(scheduler.next_runnable().is_none());
+
+        scheduler.channels.get_mut(&channel).unwrap().queue.push_back(7);
+        assert_eq!(scheduler.next_runnable().map(|t| t.id), Some(a));
+    }
+
+    #[test]
+    fn joining_a_finished_task() {
+        let mut scheduler = Scheduler::new();
+        let worker = scheduler.spawn(0x10, Vec::new());
+        let joiner = scheduler.spawn(0x20, Vec::new());
+        let worker_task = scheduler.next_runnable().unwrap();
+        assert_eq!(block(&mut scheduler, Wait::Join(worker)), joiner);
+        assert!(scheduler.next_runnable().is_none());
+
+        scheduler.finish(worker_task.id, 42);
+        assert_eq!(scheduler.result_of(worker), Some(42));
+        assert!(scheduler.exists(worker));
+        assert_eq!(scheduler.next_runnable().map(|t| t.id), Some(joiner));
+
+        // a task that already finished can be joined straight away
+        scheduler.park(Task { wait: Wait::Join(worker), ..task(joiner) });
+        assert_eq!(scheduler.next_runnable().map(|t| t.id), Some(joiner));
+        assert!(!scheduler.exists(99));
+    }
+
+    #[test]
+    fn every_task_blocked_on_a_channel_is_a_deadlock() {
+        let mut scheduler = Scheduler::new();
+        let empty = scheduler.create_channel(0);
+        let full = scheduler.create_channel(1);
+        scheduler.channels.get_mut(&full).unwrap().queue.push_back(1);
+        scheduler.spawn(0x10, Vec::new());
+        scheduler.spawn(0x20, Vec::new());
+        block(&mut scheduler, Wait::Recv(empty));
+        block(&mut scheduler, Wait::Send(full));
+        // `main` blocks too, leaving nothing to run
+        scheduler.park(Task { wait: Wait::Recv(empty), ..task(0) });
+        assert!(scheduler.next_runnable().is_none());
+
+        // closing a channel wakes everything waiting on it
+        scheduler.channels.get_mut(&full).unwrap().closed = true;
+        assert_eq!(scheduler.next_runnable().map(|t| t.id), Some(2));
+        assert!(scheduler.next_runnable().is_none());
+    }
+}
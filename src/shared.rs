@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// a range of VM memory shared between VMs running on different host
+// threads. the bytes live in 64-bit atomic words so that every thread sees
+// the same contents, and the atomic instructions map directly onto them.
+// plain loads and stores of single bytes still go through the words, so a
+// racing store never tears a neighbouring byte.
+
+pub struct SharedRegion {
+    pub base: usize,
+    pub len: usize,
+    words: Vec<AtomicU64>
+}
+
+impl SharedRegion {
+    // `base` must be 8 byte aligned and `len` is rounded up to a whole word.
+    pub fn new(base: usize, contents: &[u8]) -> Self {
+        let words = contents.chunks(8).map(|chunk| {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            AtomicU64::new(u64::from_le_bytes(word))
+        }).collect::<Vec<AtomicU64>>();
+        SharedRegion {
+            base,
+            len: words.len() * 8,
+            words
+        }
+    }
+
+    pub fn contains(self: &SharedRegion, addr: usize) -> bool {
+        addr >= self.base && addr < self.base + self.len
+    }
+
+    fn word(self: &SharedRegion, addr: usize) -> &AtomicU64 {
+        &self.words[(addr - self.base) / 8]
+    }
+
+    pub fn load_byte(self: &SharedRegion, addr: usize) -> u8 {
+        let shift = (addr - self.base) % 8 * 8;
+        (self.word(addr).load(Ordering::SeqCst) >> shift) as u8
+    }
+
+    pub fn store_byte(self: &SharedRegion, addr: usize, value: u8) {
+        let shift = (addr - self.base) % 8 * 8;
+        let _ = self.word(addr).fetch_update(Ordering::SeqCst, Ordering::SeqCst, |word| {
+            Some((word & !(0xFF << shift)) | ((value as u64) << shift))
+        });
+    }
+
+    // the word operations below expect `addr` to be 8 byte aligned.
+    pub fn load(self: &SharedRegion, addr: usize) -> u64 {
+        self.word(addr).load(Ordering::SeqCst)
+    }
+
+    pub fn store(self: &SharedRegion, addr: usize, value: u64) {
+        self.word(addr).store(value, Ordering::SeqCst);
+    }
+
+    // returns the value that was in memory; the swap happened if it equals
+    // `expected`.
+    pub fn compare_exchange(self: &SharedRegion, addr: usize, expected: u64, value: u64) -> u64 {
+        match self.word(addr).compare_exchange(expected, value, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(old) => old,
+            Err(old) => old
+        }
+    }
+
+    pub fn fetch_add(self: &SharedRegion, addr: usize, value: u64) -> u64 {
+        self.word(addr).fetch_add(value, Ordering::SeqCst)
+    }
+}
@@ -12,19 +12,22 @@ pub enum TokenType {
     VARIABLE_DECL,
     VARIABLE,
     INCLUDE,
-    CHAR
+    CHAR,
+    DIRECTIVE
 }
 
 pub struct Token {
     pub token_t: TokenType,
-    pub data: String
+    pub data: String,
+    pub line: usize
 }
 
 impl Token {
-    pub fn new(t: TokenType, d: String) -> Self {
+    pub fn new(t: TokenType, d: String, l: usize) -> Self {
         return Token {
             token_t: t,
-            data: d
+            data: d,
+            line: l
         }
     }
 }
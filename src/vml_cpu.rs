@@ -1,8 +1,30 @@
+use std::env;
 use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 use std::process;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::HashSet;
+use std::mem;
+use std::panic;
+use std::panic::AssertUnwindSafe;
 
 use crate::errors::*;
 use crate::util::*;
+use crate::debug_info::*;
+use crate::heap::*;
+use crate::handles::*;
+use crate::rng::*;
+use crate::policy::*;
+use crate::scheduler::*;
+use crate::shared::*;
+use crate::exceptions::*;
+use crate::interrupts::*;
+use crate::devices::*;
 
 // ascii table for quick string building
 
@@ -14,7 +36,36 @@ pub struct VMLCpu {
     memory: Vec<u8>,
     stack: Vec<u64>,
     pc: usize,
-    flags: u8
+    flags: u8,
+    debug_info: Option<DebugInfo>,
+    // one bit per byte of `memory`, set once the byte has been written.
+    // only allocated when the sanitizer is enabled.
+    shadow: Vec<u8>,
+    sanitize: bool,
+    sanitize_reported: HashSet<usize>,
+    heap: Heap,
+    report_leaks: bool,
+    handles: HandleTable,
+    args: Vec<String>,
+    started: Instant,
+    rng: Rng,
+    policy: Policy,
+    scheduler: Scheduler,
+    switch: Option<Switch>,
+    shared: Option<Arc<SharedRegion>>,
+    // the handle this VM was given by the `thread` syscall, 0 for the VM
+    // started from the command line
+    thread_id: u64,
+    finished: bool,
+    handlers: Vec<Handler>,
+    // handler address for each interrupt, 0 when there is none
+    vectors: Vec<usize>,
+    timer: Timer,
+    // one bit per interrupt that has arrived but not been delivered yet
+    pending: u16,
+    interrupts: Vec<InterruptFrame>,
+    // shared with every thread the program starts
+    devices: Arc<Mutex<DeviceMap>>
 }
 
 impl VMLCpu {
@@ -25,14 +76,300 @@ impl VMLCpu {
             stack: Vec::new(),
             return_stack: Vec::new(),
             pc: 0,
-            flags: 0
+            flags: 0,
+            debug_info: None,
+            shadow: Vec::new(),
+            sanitize: false,
+            sanitize_reported: HashSet::new(),
+            // until the program says where its static area ends, keep the
+            // heap in the upper half of memory.
+            heap: Heap::new(134217728 / 2, 134217728),
+            report_leaks: false,
+            handles: HandleTable::new(),
+            args: Vec::new(),
+            started: Instant::now(),
+            rng: Rng::new(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)),
+            policy: Policy::new(),
+            scheduler: Scheduler::new(),
+            switch: None,
+            shared: None,
+            thread_id: 0,
+            finished: false,
+            handlers: Vec::new(),
+            vectors: vec![0; VECTORS],
+            timer: Timer::Off,
+            pending: 0,
+            interrupts: Vec::new(),
+            devices: Arc::new(Mutex::new(DeviceMap::new()))
         }
     }
+
+    // the program's argv. by convention the first entry is the binary's name.
+    pub fn set_args(self: &mut VMLCpu, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn set_seed(self: &mut VMLCpu, seed: u64) {
+        self.rng.seed(seed);
+    }
+
+    pub fn set_policy(self: &mut VMLCpu, policy: Policy) {
+        self.policy = policy;
+    }
+
+    pub fn enable_leak_report(self: &mut VMLCpu) {
+        self.report_leaks = true;
+    }
+
+    pub fn map_device(self: &mut VMLCpu, base: usize, device: Box<dyn Device>) {
+        if !self.devices().map(base, device) {
+            format_errora(format!("The device at {:#x} overlaps another device.", base));
+            process::exit(1);
+        }
+    }
+
+    // a thread that trapped while holding the lock has already ended the
+    // program, so a poisoned map is still fine to use.
+    fn devices(self: &VMLCpu) -> MutexGuard<'_, DeviceMap> {
+        self.devices.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn set_debug_info(self: &mut VMLCpu, info: DebugInfo) {
+        self.debug_info = Some(info);
+    }
+
+    pub fn enable_sanitizer(self: &mut VMLCpu) {
+        self.sanitize = true;
+        self.shadow = vec![0; self.memory.len() / 8 + 1];
+    }
+
+    // device addresses lie past the end of the shadow and are left alone.
+    fn mark_initialized(self: &mut VMLCpu, addr: usize, len: usize) {
+        if !self.sanitize { return; }
+        for i in addr..addr + len {
+            if let Some(bits) = self.shadow.get_mut(i >> 3) { *bits |= 1 << (i & 7); }
+        }
+    }
+
+    // other threads may write the shared region at any time, so it always
+    // counts as initialized.
+    fn initialized(self: &VMLCpu, addr: usize) -> bool {
+        self.shadow.get(addr >> 3).is_none_or(|bits| bits & (1 << (addr & 7)) != 0) || self.is_shared(addr)
+    }
+
+    fn mark_uninitialized(self: &mut VMLCpu, addr: usize, len: usize) {
+        if !self.sanitize { return; }
+        for i in addr..addr + len {
+            if let Some(bits) = self.shadow.get_mut(i >> 3) { *bits &= !(1 << (i & 7)); }
+        }
+    }
+
+    // reports loads of bytes that have never been written. each faulting
+    // instruction is only reported once to avoid flooding loops.
+    fn check_initialized(self: &mut VMLCpu, addr: usize, len: usize) {
+        if !self.sanitize { return; }
+        let uninit = (addr..addr + len).filter(|i| !self.initialized(*i)).count();
+        if uninit == 0 || !self.sanitize_reported.insert(self.pc) { return; }
+
+        let source = self.debug_info.as_ref().and_then(|info| info.line_for(self.pc));
+        warningr(
+            &format!("read of {} uninitialized byte(s) in {} byte load from memory {:#010x}", uninit, len, addr),
+            self.pc,
+            source
+        );
+    }
     
+    fn is_shared(self: &VMLCpu, addr: usize) -> bool {
+        self.shared.as_ref().is_some_and(|region| region.contains(addr))
+    }
+
+    // whether a range has to be accessed byte by byte because part of it
+    // is shared or belongs to a device.
+    fn is_mapped(self: &VMLCpu, addr: usize, len: usize) -> bool {
+        addr + len > self.memory.len()
+            || self.shared.as_ref().is_some_and(|region| region.base < addr + len && addr < region.base + region.len)
+    }
+
+    // every access to `memory` goes through the functions below so that
+    // accesses to the shared region reach the copy all threads see, and
+    // accesses past the end of memory reach the devices mapped there.
+    fn load_byte(self: &VMLCpu, addr: usize) -> u8 {
+        match &self.shared {
+            Some(region) if region.contains(addr) => return region.load_byte(addr),
+            _ => ()
+        }
+        if addr >= self.memory.len() {
+            if let Some(value) = self.devices().read(addr) { return value; }
+        }
+        match self.memory.get(addr) {
+            Some(value) => *value,
+            None => self.fault(EXC_MEMORY, &format!("load outside of memory at {:#010x}", addr))
+        }
+    }
+
+    fn store_byte(self: &mut VMLCpu, addr: usize, value: u8) {
+        match &self.shared {
+            Some(region) if region.contains(addr) => return region.store_byte(addr, value),
+            _ => ()
+        }
+        if addr >= self.memory.len() && self.devices().write(addr, value) {
+            return;
+        }
+        match self.memory.get_mut(addr) {
+            Some(byte) => *byte = value,
+            None => self.fault(EXC_MEMORY, &format!("store outside of memory at {:#010x}", addr))
+        }
+    }
+
+    // little endian load of `width` bytes.
+    fn load(self: &VMLCpu, addr: usize, width: usize) -> u64 {
+        (0..width).fold(0, |val, i| val | (self.load_byte(addr + i) as u64) << (i * 8))
+    }
+
+    fn store(self: &mut VMLCpu, addr: usize, width: usize, value: u64) {
+        for i in 0..width {
+            self.store_byte(addr + i, (value >> (i * 8)) as u8);
+        }
+    }
+
+    // faults unless `len` bytes at `addr` are all in memory or all in one
+    // device, before a host buffer is sized from a length a program gave.
+    fn check_range(self: &VMLCpu, addr: usize, len: usize) {
+        if !self.in_memory(addr, len) && !self.devices().covers(addr, len) {
+            self.fault(EXC_MEMORY, &format!("{} bytes at {:#010x} are outside of memory", len, addr));
+        }
+    }
+
+    fn load_bytes(self: &VMLCpu, addr: usize, len: usize) -> Vec<u8> {
+        self.check_range(addr, len);
+        if !self.is_mapped(addr, len) {
+            return self.memory[addr..addr + len].to_vec();
+        }
+        (addr..addr + len).map(|i| self.load_byte(i)).collect()
+    }
+
+    // whether `len` bytes at `addr` are all in memory. checked before host
+    // buffers are sized from lengths a program gave.
+    fn in_memory(self: &VMLCpu, addr: usize, len: usize) -> bool {
+        addr.checked_add(len).is_some_and(|end| end <= self.memory.len())
+    }
+
+    fn store_bytes(self: &mut VMLCpu, addr: usize, bytes: &[u8]) {
+        self.check_range(addr, bytes.len());
+        if !self.is_mapped(addr, bytes.len()) {
+            self.memory[addr..addr + bytes.len()].copy_from_slice(bytes);
+            return;
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            self.store_byte(addr + i, *byte);
+        }
+    }
+
+    // the atomic instructions work on aligned 64-bit words. outside of the
+    // shared region memory belongs to this thread alone, so plain accesses
+    // are already atomic there.
+    fn atomic_addr(self: &VMLCpu, addr: usize) -> usize {
+        if !addr.is_multiple_of(8) {
+            self.fault(EXC_MEMORY, &format!("misaligned atomic access at {:#010x}", addr));
+        }
+        addr
+    }
+
+    fn atomic_load(self: &VMLCpu, addr: usize) -> u64 {
+        let addr = self.atomic_addr(addr);
+        match &self.shared {
+            Some(region) if region.contains(addr) => region.load(addr),
+            _ => self.load(addr, 8)
+        }
+    }
+
+    fn atomic_store(self: &mut VMLCpu, addr: usize, value: u64) {
+        let addr = self.atomic_addr(addr);
+        match &self.shared {
+            Some(region) if region.contains(addr) => region.store(addr, value),
+            _ => self.store(addr, 8, value)
+        }
+        self.mark_initialized(addr, 8);
+    }
+
+    fn atomic_compare_exchange(self: &mut VMLCpu, addr: usize, expected: u64, value: u64) -> u64 {
+        let addr = self.atomic_addr(addr);
+        match &self.shared {
+            Some(region) if region.contains(addr) => region.compare_exchange(addr, expected, value),
+            _ => {
+                let old = self.load(addr, 8);
+                if old == expected {
+                    self.store(addr, 8, value);
+                    self.mark_initialized(addr, 8);
+                }
+                old
+            }
+        }
+    }
+
+    fn atomic_fetch_add(self: &mut VMLCpu, addr: usize, value: u64) -> u64 {
+        let addr = self.atomic_addr(addr);
+        match &self.shared {
+            Some(region) if region.contains(addr) => region.fetch_add(addr, value),
+            _ => {
+                let old = self.load(addr, 8);
+                self.store(addr, 8, old.wrapping_add(value));
+                self.mark_initialized(addr, 8);
+                old
+            }
+        }
+    }
+
+    // starts a copy of this VM on a host thread, running the method at
+    // `addr` with `arg` on its stack. the new VM has memory of its own, apart
+    // from the shared region.
+    fn spawn_thread(self: &mut VMLCpu, addr: usize, arg: u64, rom: &[u8]) -> u64 {
+        let mut child = self.thread_vm(addr, arg);
+        let rom = rom.to_vec();
+        let id = self.handles.next_id();
+        child.thread_id = id;
+        let thread = thread::spawn(move || {
+            child.exec(&rom, &rom.len());
+            child.stack.last().copied().unwrap_or(0)
+        });
+        self.handles.insert(Handle::Thread(thread))
+    }
+
+    // the VM a new thread runs on, starting at `addr` with `arg` on its
+    // stack. it sees the shared region and the devices, but nothing else of
+    // this VM's memory.
+    fn thread_vm(self: &mut VMLCpu, addr: usize, arg: u64) -> VMLCpu {
+        let mut child = VMLCpu::new();
+        child.debug_info = self.debug_info.clone();
+        child.policy = self.policy.clone();
+        child.args = self.args.clone();
+        child.rng.seed(self.rng.next_u64());
+        if self.sanitize {
+            child.enable_sanitizer();
+        }
+        child.heap = Heap::new(self.heap.base(), 134217728);
+        if let Some(region) = &self.shared {
+            child.heap.reserve(region.base, region.len);
+            child.shared = Some(Arc::clone(region));
+        }
+        child.devices = Arc::clone(&self.devices);
+        child.pc = addr;
+        child.stack.push(arg);
+        child
+    }
+
+    // a byte of the program, for operands and strings read from the ROM.
+    fn rom_byte(self: &VMLCpu, index: usize, rom: &Vec<u8>) -> u8 {
+        match rom.get(index) {
+            Some(byte) => *byte,
+            None => self.fault(EXC_MEMORY, &format!("read past the end of the program at {:#010x}", index))
+        }
+    }
+
     pub fn read_u64(self: &VMLCpu, index: usize, rom: &Vec<u8>) -> u64 {
         let mut val: u64 = 0;
         for i in 0..8 {
-            val += (rom[index + i as usize] as u64) << (i * 8);
+            val += (self.rom_byte(index + i as usize, rom) as u64) << (i * 8);
         }
 
         return val;
@@ -41,7 +378,7 @@ impl VMLCpu {
     pub fn read_usize(self: &VMLCpu, index: usize, rom: &Vec<u8>) -> usize {
         let mut val: usize = 0;
         for i in 0..4 {
-            val += (rom[index + i as usize] as usize) << (i * 8);
+            val += (self.rom_byte(index + i as usize, rom) as usize) << (i * 8);
         }
         
         return val;
@@ -51,7 +388,7 @@ impl VMLCpu {
     pub fn read_NTString(self: &VMLCpu, index: usize, rom: &Vec<u8>) -> String {
         let mut ret: String = String::new();
         let mut ind: usize = index;
-        while rom[ind] != 0 {
+        while self.rom_byte(ind, rom) != 0 {
             ret += ASCII[rom[ind] as usize];
             ind += 1;
         }
@@ -62,17 +399,244 @@ impl VMLCpu {
     pub fn read_buffered_NTString(self: &VMLCpu, index: usize) -> String {
         let mut ret: String = String::new();
         let mut ind: usize = index;
-        while self.memory[ind] != 0 {
-            ret += ASCII[self.memory[ind] as usize];
+        while self.load_byte(ind) != 0 {
+            ret += ASCII[self.load_byte(ind) as usize];
             ind += 1;
         }
         return ret;
     }
 
+    // reads a filename (or any other string argument) from the ROM
+    // (context 0) or from a buffer in memory (context 1).
+    fn read_name(self: &VMLCpu, context: u64, addr: usize, rom: &Vec<u8>) -> Option<String> {
+        match context {
+            0 => Some(self.read_NTString(addr, rom)),
+            1 => Some(self.read_buffered_NTString(addr)),
+            _ => None
+        }
+    }
+
+    // copies a string into memory with a NUL terminator.
+    #[allow(non_snake_case)]
+    fn write_buffered_NTString(self: &mut VMLCpu, index: usize, value: &[u8]) {
+        self.store_bytes(index, value);
+        self.store_byte(index + value.len(), 0x00);
+        self.mark_initialized(index, value.len() + 1);
+    }
+
+    // resolves a host and port popped off of the stack for the socket
+    // syscalls, keeping only the addresses the policy allows.
+    fn socket_addresses(self: &mut VMLCpu, rom: &Vec<u8>) -> Result<Vec<SocketAddr>, u64> {
+        let port = self.pop();
+        let host_addr = self.pop() as usize;
+        let context = self.pop();
+
+        let host = self.read_name(context, host_addr, rom).ok_or(STATUS_INVALID)?;
+        let port = u16::try_from(port).map_err(|_| STATUS_INVALID)?;
+        let addresses: Vec<SocketAddr> = (host.as_str(), port).to_socket_addrs().map_err(|e| io_status(&e))?.collect();
+        let allowed: Vec<SocketAddr> = addresses.into_iter().filter(|a| self.policy.allows_address(a)).collect();
+        if allowed.is_empty() {
+            return Err(STATUS_PERMISSION_DENIED);
+        }
+        Ok(allowed)
+    }
+
+    fn push_result(self: &mut VMLCpu, value: u64, status: u64) {
+        self.stack.push(value);
+        self.stack.push(status);
+    }
+
     pub fn exec(self: &mut VMLCpu, rom: &Vec<u8>, code_len: &usize) {
+        // faults inside an instruction unwind out of `run` as a `Fault`
+        // panic. each one goes to the innermost `try` handler and execution
+        // carries on from there, or is reported as a trap with the VM state
+        // instead of a bare panic message. any other panic is a bug in the
+        // VM rather than in the program, so it can not be caught.
+        let was_in_vm = set_in_vm(true);
+        while let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.run(rom, code_len))) {
+            let fault = match payload.downcast::<Fault>() {
+                Ok(fault) => *fault,
+                Err(payload) => {
+                    set_in_vm(was_in_vm);
+                    let message = match payload.downcast_ref::<&str>() {
+                        Some(s) => s.to_string(),
+                        None => payload.downcast_ref::<String>().cloned().unwrap_or_default()
+                    };
+                    self.trap(&format!("internal error in the VM: {}", message));
+                }
+            };
+            if !self.throw(fault.code) {
+                set_in_vm(was_in_vm);
+                self.trap(&fault.reason);
+            }
+        }
+        set_in_vm(was_in_vm);
+
+        // threads share the devices of the VM that started them
+        if self.thread_id == 0 {
+            self.devices().finish();
+        }
+        if self.report_leaks {
+            self.leak_report();
+        }
+    }
+
+    fn leak_report(self: &VMLCpu) {
+        if self.heap.blocks.is_empty() { return; }
+        let total: usize = self.heap.blocks.values().map(|block| block.size).sum();
+        warninga(&format!("{} heap block(s) ({} bytes) were never freed:", self.heap.blocks.len(), total));
+        for (addr, block) in &self.heap.blocks {
+            println!("	{:#010x} ({} bytes) allocated at {}", addr, block.size, self.format_backtrace(&block.site));
+            let last = *block.site.last().unwrap();
+            if let Some((line, text)) = self.debug_info.as_ref().and_then(|info| info.line_for(last)) {
+                println!("		line {}: {}", line, text.trim());
+            }
+        }
+    }
+
+    fn pop(self: &mut VMLCpu) -> u64 {
+        match self.stack.pop() {
+            Some(value) => value,
+            None => self.fault(EXC_STACK_UNDERFLOW, "stack underflow")
+        }
+    }
+
+    // raises a runtime error that a `try` handler can catch.
+    fn fault(self: &VMLCpu, code: u64, reason: &str) -> ! {
+        panic::panic_any(Fault { code, reason: reason.to_string() })
+    }
+
+    // unwinds to the innermost handler, leaving `code` on top of the stack.
+    // returns false when there is no handler to catch it.
+    fn throw(self: &mut VMLCpu, code: u64) -> bool {
+        match self.handlers.pop() {
+            Some(handler) => {
+                self.stack.truncate(handler.stack_depth);
+                self.return_stack.truncate(handler.return_depth);
+                self.interrupts.truncate(handler.interrupt_depth);
+                self.stack.push(code);
+                self.pc = handler.pc;
+                true
+            },
+            None => false
+        }
+    }
+
+    // reports a fatal runtime error together with a backtrace built from the
+    // return stack, the top of the data stack and the faulting source line.
+    fn trap(self: &VMLCpu, reason: &str) -> ! {
+        let mut reason = reason.to_string();
+        if self.thread_id != 0 {
+            reason += &format!(" (in thread {})", self.thread_id);
+        }
+        if self.scheduler.current != 0 {
+            reason += &format!(" (in task {})", self.scheduler.current);
+        }
+        format_errorr(&reason, self.pc, self.debug_info.as_ref().and_then(|i| i.line_for(self.pc)));
+
+        eprintln!("Backtrace: {}", self.format_backtrace(&self.call_sites()));
+
+        let top: Vec<String> = self.stack.iter().rev().take(8).map(|v| format!("{:#x}", v)).collect();
+        eprintln!("Stack ({} values, top first): [{}]", self.stack.len(), top.join(", "));
+        // the program ends here, so save what the devices have collected
+        self.devices().finish();
+        process::exit(1);
+    }
+
+    // the address of every active `jsr`, outermost first, followed by the
+    // current pc. each return address points just past a 6 byte `jsr`.
+    fn call_sites(self: &VMLCpu) -> Vec<usize> {
+        self.return_stack.iter().map(|r| r.saturating_sub(6)).chain([self.pc]).collect()
+    }
+
+    fn format_backtrace(self: &VMLCpu, sites: &[usize]) -> String {
+        let mut frames: Vec<String> = Vec::new();
+        for addr in sites {
+            match &self.debug_info {
+                Some(info) => if let Some(name) = info.method_for(*addr) { frames.push(name.to_string()); },
+                None => frames.push(format!("{:#010x}", addr))
+            }
+        }
+        frames.join(" -> ")
+    }
+
+    // delivers the lowest pending interrupt that has a handler. a handler is
+    // never interrupted itself; anything that arrives meanwhile waits for
+    // its IRET.
+    fn poll_interrupts(self: &mut VMLCpu) {
+        if self.timer.tick() {
+            self.pending |= 1 << IRQ_TIMER;
+        }
+        if self.vectors[IRQ_SIGNAL] != 0 && take_signal() {
+            self.pending |= 1 << IRQ_SIGNAL;
+        }
+        if self.pending == 0 || !self.interrupts.is_empty() { return; }
+
+        let irq = self.pending.trailing_zeros() as usize;
+        self.pending &= !(1 << irq);
+        if self.vectors[irq] != 0 {
+            self.enter_interrupt(irq, self.pc);
+        }
+    }
+
+    fn enter_interrupt(self: &mut VMLCpu, irq: usize, return_pc: usize) {
+        self.interrupts.push(InterruptFrame {
+            pc: return_pc,
+            flags: self.flags,
+            registers: self.registers.clone()
+        });
+        self.pc = self.vectors[irq];
+    }
+
+    // parks the running task and resumes the next one that is able to run.
+    fn switch_task(self: &mut VMLCpu, switch: Switch, start_pc: usize) {
+        let wait = match switch {
+            Switch::Yield => Wait::Nothing,
+            Switch::Block(wait) => {
+                self.pc = start_pc;
+                wait
+            },
+            Switch::Exit => {
+                let result = self.stack.last().copied().unwrap_or(0);
+                self.scheduler.finish(self.scheduler.current, result);
+                return self.resume_next();
+            }
+        };
+        self.scheduler.park(Task {
+            id: self.scheduler.current,
+            pc: self.pc,
+            registers: mem::take(&mut self.registers),
+            stack: mem::take(&mut self.stack),
+            return_stack: mem::take(&mut self.return_stack),
+            handlers: mem::take(&mut self.handlers),
+            interrupts: mem::take(&mut self.interrupts),
+            flags: self.flags,
+            wait
+        });
+        self.resume_next();
+    }
+
+    fn resume_next(self: &mut VMLCpu) {
+        match self.scheduler.next_runnable() {
+            Some(task) => {
+                self.pc = task.pc;
+                self.registers = task.registers;
+                self.stack = task.stack;
+                self.return_stack = task.return_stack;
+                self.handlers = task.handlers;
+                self.interrupts = task.interrupts;
+                self.flags = task.flags;
+            },
+            None => self.trap("deadlock: every task is waiting on another")
+        }
+    }
+
+    fn run(self: &mut VMLCpu, rom: &Vec<u8>, code_len: &usize) {
         let mut args: u8;
         let mut jump_amnt: usize;
-        while (self.pc+1 < *code_len) && (self.flags & 0x01 == 0x00){
+        let mut start_pc: usize;
+        while (self.pc+1 < *code_len) && (self.flags & 0x01 == 0x00) && !self.finished {
+            start_pc = self.pc;
             args = rom[self.pc + 1];
             jump_amnt = 2;
             match &rom[self.pc] {
@@ -81,16 +645,18 @@ impl VMLCpu {
                     self.pc += 8;
                 },
                 0x01 => {
+                    self.check_initialized(self.read_usize(self.pc + 2, rom), 1);
                     self.registers[
-                        (args & 0x0F) as usize] = self.memory[self.read_usize(
+                        (args & 0x0F) as usize] = self.load_byte(self.read_usize(
                             self.pc + 2,
-                            rom)] as u64;
+                            rom)) as u64;
                     self.pc += 4;
                 },
                 0x02 => {
-                    self.registers[(args & 0x0F) as usize] = self.memory[self.read_usize(
+                    self.check_initialized(self.read_usize(self.pc + 2, rom) + self.registers[((args & 0xF0) >> 4) as usize] as usize, 1);
+                    self.registers[(args & 0x0F) as usize] = self.load_byte(self.read_usize(
                         self.pc + 2,
-                        rom) as usize + (self.registers[((args & 0xF0) >> 4) as usize]) as usize] as u64;
+                        rom) as usize + (self.registers[((args & 0xF0) >> 4) as usize]) as usize) as u64;
                     self.pc += 4;
                 },
                 0x03 => {
@@ -99,20 +665,22 @@ impl VMLCpu {
                 },
                 0x04 => {
                     let mem = self.read_usize(self.pc + 2, rom);
-                    self.memory[mem] = (self.registers[(
-                        args & 0x0F) as usize] & 0xFF) as u8;
+                    self.store_byte(mem, (self.registers[(
+                        args & 0x0F) as usize] & 0xFF) as u8);
+                    self.mark_initialized(mem, 1);
                     self.pc += 4;
                 },
                 0x05 => {
                     let mem = self.read_usize(self.pc + 2, rom) + self.registers[((args & 0xf0) >> 4) as usize] as usize;
-                    self.memory[mem] = (self.registers[(args & 0x0F) as usize] & 0xFF) as u8;
+                    self.store_byte(mem, (self.registers[(args & 0x0F) as usize] & 0xFF) as u8);
+                    self.mark_initialized(mem, 1);
                     self.pc += 4;
                 },
                 0x06 => {
                     self.stack.push(self.registers[(args & 0x0F) as usize]);
                 },
                 0x07 => {
-                    self.registers[(args & 0x0F) as usize] = self.stack.pop().unwrap();
+                    self.registers[(args & 0x0F) as usize] = self.pop();
                 },
                 0x08 => {
                     self.registers[(args & 0x0F) as usize] = self.registers[(args & 0x0F) as usize] + self.registers[
@@ -127,6 +695,9 @@ impl VMLCpu {
                         ((args & 0xF0) >> 4) as usize];
                 },
                 0x0B => {
+                    if self.registers[((args & 0xF0) >> 4) as usize] == 0 {
+                        self.fault(EXC_DIVIDE_BY_ZERO, "integer division by zero");
+                    }
                     self.registers[(args & 0x0F) as usize] = self.registers[(args & 0x0F) as usize] / self.registers[
                         ((args & 0xF0) >> 4) as usize];
                 },
@@ -223,7 +794,24 @@ impl VMLCpu {
                     jump_amnt = 0;
                 },
                 0x1F => {
-                    self.pc = self.return_stack.pop().unwrap();
+                    self.pc = match self.return_stack.pop() {
+                        Some(addr) => addr,
+                        // a spawned task returning from its entry method
+                        None if self.scheduler.current != 0 => {
+                            self.switch = Some(Switch::Exit);
+                            self.pc
+                        },
+                        // the entry method of a thread returning
+                        None if self.thread_id != 0 => {
+                            self.finished = true;
+                            self.pc
+                        },
+                        None => self.fault(EXC_STACK_UNDERFLOW, "return with an empty return stack")
+                    };
+                    // drop the handlers of `try` blocks the method left early
+                    while self.handlers.last().is_some_and(|h| h.return_depth > self.return_stack.len()) {
+                        self.handlers.pop();
+                    }
                     jump_amnt = 0;
                 },
                 0x20 => {
@@ -238,56 +826,47 @@ impl VMLCpu {
                     self.pc += 4;
                 },
                 0x24 => {
-                    self.registers[(args & 0x0F) as usize] = self.memory[(self.registers[((args & 0xF0) >> 4) as usize]) as usize] as u64;
+                    self.check_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 1);
+                    self.registers[(args & 0x0F) as usize] = self.load_byte(self.registers[((args & 0xF0) >> 4) as usize] as usize) as u64;
                 }
                 0x25 => {
-                    let mut val: u64 = 0;
-                    for i in 0..2 {
-                        val += (self.memory[(self.registers[((args & 0xF0) >> 4) as usize] + i) as usize] as u64) << (i * 8);
-                    }
-                    self.registers[(args & 0x0F) as usize] = val;
+                    self.check_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 2);
+                    self.registers[(args & 0x0F) as usize] = self.load(self.registers[((args & 0xF0) >> 4) as usize] as usize, 2);
                 },
                 0x26 => {
-                    let mut val: u64 = 0;
-                    for i in 0..4 {
-                        val += (self.memory[(self.registers[((args & 0xF0) >> 4) as usize] + i) as usize] as u64) << (i * 8);
-                    }
-                    self.registers[(args & 0x0F) as usize] = val;
+                    self.check_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 4);
+                    self.registers[(args & 0x0F) as usize] = self.load(self.registers[((args & 0xF0) >> 4) as usize] as usize, 4);
                 }
                 0x27 => {
-                    let mut val: u64 = 0;
-                    for i in 0..8 {
-                        val += (self.memory[(self.registers[((args & 0xF0) >> 4) as usize] + i) as usize] as u64) << (i * 8);
-                    }
-                    self.registers[(args & 0x0F) as usize] = val;
+                    self.check_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 8);
+                    self.registers[(args & 0x0F) as usize] = self.load(self.registers[((args & 0xF0) >> 4) as usize] as usize, 8);
                 }
                 0x28 => {
-                    self.memory[(self.registers[((args & 0xF0) >> 4) as usize]) as usize] = self.registers[(args & 0x0F) as usize] as u8;
+                    self.store_byte(self.registers[((args & 0xF0) >> 4) as usize] as usize, self.registers[(args & 0x0F) as usize] as u8);
+                    self.mark_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 1);
                 }
                 0x29 => {
-                    for i in 0..2 {
-                        self.memory[(self.registers[((args & 0xF0) >> 4) as usize] + i) as usize] = (self.registers[(args & 0x0F) as usize] >> (i * 8)) as u8;
-                    }
+                    self.store(self.registers[((args & 0xF0) >> 4) as usize] as usize, 2, self.registers[(args & 0x0F) as usize]);
+                    self.mark_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 2);
                 },
                 0x2A => {
-                    for i in 0..4 {
-                        self.memory[(self.registers[((args & 0xF0) >> 4) as usize] + i) as usize] = (self.registers[(args & 0x0F) as usize] >> (i * 8)) as u8;
-                    }
+                    self.store(self.registers[((args & 0xF0) >> 4) as usize] as usize, 4, self.registers[(args & 0x0F) as usize]);
+                    self.mark_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 4);
                 }
                 0x2B => {
-                    for i in 0..8 {
-                        self.memory[(self.registers[((args & 0xF0) >> 4) as usize] + i) as usize] = (self.registers[(args & 0x0F) as usize] >> (i * 8)) as u8;
-                    }
+                    self.store(self.registers[((args & 0xF0) >> 4) as usize] as usize, 8, self.registers[(args & 0x0F) as usize]);
+                    self.mark_initialized(self.registers[((args & 0xF0) >> 4) as usize] as usize, 8);
                 },
                 0x2C => {
                     let loc = self.registers[(args & 0x0F) as usize] as usize;
                     let dest = self.registers[((args & 0xF0) >> 4) as usize] as usize;
                     let mut i: usize = 0;
 
-                    while rom[loc + i] != 0x00 {
-                        self.memory[dest + i] = rom[loc + i];
+                    while self.rom_byte(loc + i, rom) != 0x00 {
+                        self.store_byte(dest + i, rom[loc + i]);
                         i += 1;
                     }
+                    self.mark_initialized(dest, i);
                 },
                 0x2D => {
                     let loc = self.registers[(args & 0x0F) as usize] as usize;
@@ -318,9 +897,66 @@ impl VMLCpu {
                 0x31 => {
                     self.handle_syscalls(self.registers[(args & 0x0F) as usize] as usize, rom);
                 },
-                _ => eprintln!("Unrecognized opcode at {:#018x} (ERR)", self.pc)
+                0x32 => {
+                    self.registers[(args & 0x0F) as usize] = self.atomic_load(self.registers[((args & 0xF0) >> 4) as usize] as usize);
+                },
+                0x33 => {
+                    self.atomic_store(self.registers[((args & 0xF0) >> 4) as usize] as usize, self.registers[(args & 0x0F) as usize]);
+                },
+                0x34 => {
+                    let expected = self.registers[0];
+                    let old = self.atomic_compare_exchange(self.registers[((args & 0xF0) >> 4) as usize] as usize, expected, self.registers[(args & 0x0F) as usize]);
+                    self.flags = if old == expected { 0b00000100 } else { 0 };
+                    self.registers[0] = old;
+                },
+                0x35 => {
+                    self.registers[(args & 0x0F) as usize] = self.atomic_fetch_add(self.registers[((args & 0xF0) >> 4) as usize] as usize, self.registers[(args & 0x0F) as usize]);
+                },
+                0x36 => {
+                    self.handlers.push(Handler {
+                        pc: self.read_usize(self.pc + 2, rom),
+                        stack_depth: self.stack.len(),
+                        return_depth: self.return_stack.len(),
+                        interrupt_depth: self.interrupts.len()
+                    });
+                    self.pc += 4;
+                },
+                0x37 => {
+                    self.handlers.pop();
+                },
+                0x38 => {
+                    let code = self.registers[(args & 0x0F) as usize];
+                    if !self.throw(code) {
+                        self.trap(&format!("uncaught exception {:#x}", code));
+                    }
+                    jump_amnt = 0;
+                },
+                0x39 => {
+                    let irq = self.registers[(args & 0x0F) as usize] as usize;
+                    if irq >= VECTORS || self.vectors[irq] == 0 {
+                        self.fault(EXC_BAD_INSTRUCTION, &format!("interrupt {} has no handler", irq));
+                    }
+                    self.enter_interrupt(irq, self.pc + 2);
+                    jump_amnt = 0;
+                },
+                0x3A => {
+                    match self.interrupts.pop() {
+                        Some(frame) => {
+                            self.pc = frame.pc;
+                            self.flags = frame.flags;
+                            self.registers = frame.registers;
+                        },
+                        None => self.fault(EXC_BAD_INSTRUCTION, "iret outside of an interrupt handler")
+                    }
+                    jump_amnt = 0;
+                },
+                _ => self.fault(EXC_BAD_INSTRUCTION, &format!("unrecognized opcode {:#04x}", rom[self.pc]))
             }
             self.pc += jump_amnt;
+            if let Some(switch) = self.switch.take() {
+                self.switch_task(switch, start_pc);
+            }
+            self.poll_interrupts();
             if (self.flags & 0b10000000) != 0 {
                 loop {}
             }
@@ -343,65 +979,597 @@ impl VMLCpu {
 
     fn handle_syscalls(self: &mut VMLCpu, syscall: usize, rom: &Vec<u8>) {
         match &syscall {
-            0x00 => print!("{}", self.stack.pop().unwrap()),
+            0x00 => print!("{}", self.pop()),
             0x01 => {
-                let addr: usize = self.stack.pop().unwrap() as usize;
+                let addr: usize = self.pop() as usize;
                 print!("{}", self.read_NTString(addr, rom));
             },
-            0x02 => print!("{:#064b}", self.stack.pop().unwrap()),
-            0x03 => print!("{:#018x}", self.stack.pop().unwrap()),
+            0x02 => print!("{:#064b}", self.pop()),
+            0x03 => print!("{:#018x}", self.pop()),
             0x04 => {
-                let addr: usize = self.stack.pop().unwrap() as usize;
+                let addr: usize = self.pop() as usize;
                 print!("{}", self.read_buffered_NTString(addr));
             },
             0x05 => {
-                let buffer: usize = self.stack.pop().unwrap() as usize;
+                let buffer: usize = self.pop() as usize;
                 let mut input: String = String::new();
                 std::io::stdin().read_line(&mut input).unwrap();
                 for i in 0..input.len()-1 {
-                    self.memory[buffer + i] = input.as_bytes()[i];
+                    self.store_byte(buffer + i, input.as_bytes()[i]);
                 }
-                self.memory[buffer + input.len()] = 0x00;
+                self.store_byte(buffer + input.len(), 0x00);
+                self.mark_initialized(buffer, input.len() + 1);
             },
-            0x06 => print!("{}", to_f64(self.stack.pop().unwrap())),
-            0x07 => print!("{}", self.stack.pop().unwrap() as i64),
+            0x06 => print!("{}", to_f64(self.pop())),
+            0x07 => print!("{}", self.pop() as i64),
             0x08 => {
-                let file_addr = self.stack.pop();
-                let buffer = self.stack.pop();
-                let context = self.stack.pop();
+                let file_addr = self.pop();
+                let buffer = self.pop();
+                let context = self.pop();
 
                 let mut filename: String = String::new();
-                match &context.unwrap() {
-                    0 => filename = self.read_NTString(file_addr.unwrap() as usize, rom),
-                    1 => filename = self.read_buffered_NTString(file_addr.unwrap() as usize),
-                    _ => eprintln!("Unknown FileBuffer read type: {}.", context.unwrap()),
+                match &context {
+                    0 => filename = self.read_NTString(file_addr as usize, rom),
+                    1 => filename = self.read_buffered_NTString(file_addr as usize),
+                    _ => eprintln!("Unknown FileBuffer read type: {}.", context),
                 }
 
                 let filecontents = fs::read_to_string(filename).expect("Failed to read file.");
                 let filec_buf = filecontents.as_bytes();
-                for i in 0..filec_buf.len() {
-                    self.memory[(buffer.unwrap() as usize) + i] = filec_buf[i];
-                }
+                self.store_bytes(buffer as usize, filec_buf);
+                self.mark_initialized(buffer as usize, filec_buf.len());
             },
             0x09 => {
-                let file_addr = self.stack.pop();
-                let buffer = self.stack.pop();
-                let context = self.stack.pop();
+                let file_addr = self.pop();
+                let buffer = self.pop();
+                let context = self.pop();
                 
                 let mut filename: String = String::new();
-                match &context.unwrap() {
-                    0 => filename = self.read_NTString(file_addr.unwrap() as usize, rom),
-                    1 => filename = self.read_buffered_NTString(file_addr.unwrap() as usize),
-                    _ => eprintln!("Unknown FileBuffer write type: {}.", context.unwrap()),
+                match &context {
+                    0 => filename = self.read_NTString(file_addr as usize, rom),
+                    1 => filename = self.read_buffered_NTString(file_addr as usize),
+                    _ => eprintln!("Unknown FileBuffer write type: {}.", context),
                 }
 
-                fs::write(filename, &*self.read_buffered_NTString(buffer.unwrap() as usize))
+                fs::write(filename, &*self.read_buffered_NTString(buffer as usize))
                     .expect("Unable to write to file!");
             },
-            _    => {
-                format_errora("Error - unrecognized SYSCALL. Perhaps you're missing an extension?".to_string());
-                process::exit(1);
+            0x0A => {
+                let size = self.pop() as usize;
+                let site = self.call_sites();
+                match self.heap.alloc(size, site) {
+                    Some(addr) => {
+                        self.mark_uninitialized(addr, size);
+                        self.stack.push(addr as u64);
+                    },
+                    None => self.stack.push(0)
+                }
+            },
+            0x0B => {
+                let addr = self.pop() as usize;
+                if addr != 0 && self.heap.free(addr).is_none() {
+                    self.fault(EXC_HEAP, &format!("free of {:#010x}, which is not an allocated heap block", addr));
+                }
+            },
+            0x0C => {
+                let size = self.pop() as usize;
+                let addr = self.pop() as usize;
+                if addr == 0 {
+                    self.stack.push(size as u64);
+                    return self.handle_syscalls(0x0A, rom);
+                }
+                let old_size = match self.heap.size_of(addr) {
+                    Some(old_size) => old_size,
+                    None => self.fault(EXC_HEAP, &format!("realloc of {:#010x}, which is not an allocated heap block", addr))
+                };
+                if self.heap.resize_in_place(addr, size) {
+                    if size > old_size { self.mark_uninitialized(addr + old_size, size - old_size); }
+                    self.stack.push(addr as u64);
+                    return;
+                }
+                let site = self.call_sites();
+                match self.heap.alloc(size, site) {
+                    Some(new_addr) => {
+                        let kept = old_size.min(size);
+                        let contents = self.load_bytes(addr, kept);
+                        self.store_bytes(new_addr, &contents);
+                        self.mark_uninitialized(new_addr, size);
+                        for i in 0..kept {
+                            if self.sanitize && self.initialized(addr + i) {
+                                self.mark_initialized(new_addr + i, 1);
+                            }
+                        }
+                        self.heap.free(addr);
+                        self.stack.push(new_addr as u64);
+                    },
+                    None => self.stack.push(0)
+                }
+            },
+            0x0D => {
+                let base = self.pop() as usize;
+                if !self.heap.set_base(base) {
+                    self.fault(EXC_HEAP, "the heap base can only be moved before the first allocation");
+                }
+            },
+            0x0E => {
+                let mode = self.pop();
+                let name_addr = self.pop() as usize;
+                let context = self.pop();
+
+                let mut options = OpenOptions::new();
+                match mode {
+                    0 => options.read(true),
+                    1 => options.write(true).create(true).truncate(true),
+                    2 => options.append(true).create(true),
+                    3 => options.read(true).write(true).create(true),
+                    _ => return self.push_result(0, STATUS_INVALID)
+                };
+                let filename = match self.read_name(context, name_addr, rom) {
+                    Some(filename) => filename,
+                    None => return self.push_result(0, STATUS_INVALID)
+                };
+                match options.open(filename) {
+                    Ok(file) => {
+                        let handle = self.handles.insert(Handle::File(file));
+                        self.push_result(handle, STATUS_OK);
+                    },
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x0F => {
+                let len = self.pop() as usize;
+                let buffer = self.pop() as usize;
+                let handle = self.pop();
+
+                if !self.in_memory(buffer, len) {
+                    return self.push_result(0, STATUS_INVALID);
+                }
+                let mut data = vec![0; len];
+                let result = match self.handles.get(handle) {
+                    Some(Handle::File(file)) => file.read(&mut data),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok(count) => {
+                        self.store_bytes(buffer, &data[..count]);
+                        self.mark_initialized(buffer, count);
+                        self.push_result(count as u64, STATUS_OK);
+                    },
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x10 => {
+                let len = self.pop() as usize;
+                let buffer = self.pop() as usize;
+                let handle = self.pop();
+
+                let data = self.load_bytes(buffer, len);
+                let result = match self.handles.get(handle) {
+                    Some(Handle::File(file)) => file.write(&data),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok(count) => self.push_result(count as u64, STATUS_OK),
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x11 => {
+                let whence = self.pop();
+                let offset = self.pop();
+                let handle = self.pop();
+
+                let position = match whence {
+                    0 => SeekFrom::Start(offset),
+                    1 => SeekFrom::Current(offset as i64),
+                    2 => SeekFrom::End(offset as i64),
+                    _ => return self.push_result(0, STATUS_INVALID)
+                };
+                let result = match self.handles.get(handle) {
+                    Some(Handle::File(file)) => file.seek(position),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok(position) => self.push_result(position, STATUS_OK),
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x12 => {
+                let handle = self.pop();
+                match self.handles.remove(handle) {
+                    Some(_) => self.stack.push(STATUS_OK),
+                    None => self.stack.push(STATUS_BAD_HANDLE)
+                }
+            },
+            0x13 => {
+                let name_addr = self.pop() as usize;
+                let context = self.pop();
+
+                let filename = match self.read_name(context, name_addr, rom) {
+                    Some(filename) => filename,
+                    None => return self.push_result(0, STATUS_INVALID)
+                };
+                match fs::metadata(filename) {
+                    Ok(meta) => self.push_result(meta.len(), STATUS_OK),
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x14 => self.stack.push(self.args.len() as u64),
+            0x15 => {
+                let buffer = self.pop() as usize;
+                let index = self.pop() as usize;
+                match self.args.get(index).cloned() {
+                    Some(arg) => {
+                        self.write_buffered_NTString(buffer, arg.as_bytes());
+                        self.push_result(arg.len() as u64, STATUS_OK);
+                    },
+                    None => self.push_result(0, STATUS_INVALID)
+                }
+            },
+            0x16 => {
+                let buffer = self.pop() as usize;
+                let name_addr = self.pop() as usize;
+                let context = self.pop();
+
+                let name = match self.read_name(context, name_addr, rom) {
+                    Some(name) => name,
+                    None => return self.push_result(0, STATUS_INVALID)
+                };
+                match env::var_os(name) {
+                    Some(value) => {
+                        let value = value.to_string_lossy().into_owned();
+                        self.write_buffered_NTString(buffer, value.as_bytes());
+                        self.push_result(value.len() as u64, STATUS_OK);
+                    },
+                    None => self.push_result(0, STATUS_NOT_FOUND)
+                }
+            },
+            0x17 => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+                self.stack.push(now);
+            },
+            0x18 => self.stack.push(self.started.elapsed().as_nanos() as u64),
+            0x19 => {
+                let millis = self.pop();
+                thread::sleep(Duration::from_millis(millis));
+            },
+            0x1A => {
+                let seed = self.pop();
+                self.rng.seed(seed);
+            },
+            0x1B => {
+                let value = self.rng.next_u64();
+                self.stack.push(value);
+            },
+            0x1C => {
+                // the top 53 bits give every double in [0, 1) with a step of 2^-53
+                let value = (self.rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+                self.stack.push(to_u64(value));
+            },
+            0x1D => {
+                let addresses = match self.socket_addresses(rom) {
+                    Ok(addresses) => addresses,
+                    Err(status) => return self.push_result(0, status)
+                };
+                match TcpListener::bind(&addresses[..]) {
+                    Ok(listener) => {
+                        let handle = self.handles.insert(Handle::Listener(listener));
+                        self.push_result(handle, STATUS_OK);
+                    },
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x1E => {
+                let handle = self.pop();
+                let result = match self.handles.get(handle) {
+                    Some(Handle::Listener(listener)) => listener.accept(),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok((stream, peer)) if self.policy.allows_address(&peer) => {
+                        let handle = self.handles.insert(Handle::Stream(stream));
+                        self.push_result(handle, STATUS_OK);
+                    },
+                    Ok(_) => self.push_result(0, STATUS_PERMISSION_DENIED),
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x1F => {
+                let addresses = match self.socket_addresses(rom) {
+                    Ok(addresses) => addresses,
+                    Err(status) => return self.push_result(0, status)
+                };
+                match TcpStream::connect(&addresses[..]) {
+                    Ok(stream) => {
+                        let handle = self.handles.insert(Handle::Stream(stream));
+                        self.push_result(handle, STATUS_OK);
+                    },
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x20 => {
+                let len = self.pop() as usize;
+                let buffer = self.pop() as usize;
+                let handle = self.pop();
+
+                let data = self.load_bytes(buffer, len);
+                let result = match self.handles.get(handle) {
+                    Some(Handle::Stream(stream)) => stream.write(&data),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok(count) => self.push_result(count as u64, STATUS_OK),
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x21 => {
+                let len = self.pop() as usize;
+                let buffer = self.pop() as usize;
+                let handle = self.pop();
+
+                if !self.in_memory(buffer, len) {
+                    return self.push_result(0, STATUS_INVALID);
+                }
+                let mut data = vec![0; len];
+                let result = match self.handles.get(handle) {
+                    Some(Handle::Stream(stream)) => stream.read(&mut data),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok(count) => {
+                        self.store_bytes(buffer, &data[..count]);
+                        self.mark_initialized(buffer, count);
+                        self.push_result(count as u64, STATUS_OK);
+                    },
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x22 => {
+                let handle = self.pop();
+                let result = match self.handles.get(handle) {
+                    Some(Handle::Listener(listener)) => listener.local_addr(),
+                    Some(Handle::Stream(stream)) => stream.local_addr(),
+                    _ => return self.push_result(0, STATUS_BAD_HANDLE)
+                };
+                match result {
+                    Ok(addr) => self.push_result(addr.port() as u64, STATUS_OK),
+                    Err(e) => self.push_result(0, io_status(&e))
+                }
+            },
+            0x23 => {
+                let context = self.pop();
+                let out_len = self.pop() as usize;
+                let out = self.pop() as usize;
+                let argc = self.pop() as usize;
+                let argv = self.pop() as usize;
+
+                if !self.policy.allow_exec {
+                    self.stack.push(0);
+                    return self.push_result(0, STATUS_PERMISSION_DENIED);
+                }
+                // argv is an array of argc 64-bit string addresses, the first
+                // of which names the command.
+                let mut command: Vec<String> = Vec::new();
+                for i in 0..argc {
+                    let addr = self.load(argv + i * 8, 8) as usize;
+                    match self.read_name(context, addr, rom) {
+                        Some(arg) => command.push(arg),
+                        None => {
+                            self.stack.push(0);
+                            return self.push_result(0, STATUS_INVALID);
+                        }
+                    }
+                }
+                if command.is_empty() {
+                    self.stack.push(0);
+                    return self.push_result(0, STATUS_INVALID);
+                }
+
+                let output = Command::new(&command[0])
+                    .args(&command[1..])
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit())
+                    .output();
+                match output {
+                    Ok(output) => {
+                        let len = output.stdout.len().min(out_len);
+                        self.store_bytes(out, &output.stdout[..len]);
+                        self.mark_initialized(out, len);
+                        // killed by a signal: there is no exit code to report
+                        let code = output.status.code().map_or(u64::MAX, |c| i64_bits(c as i64));
+                        self.stack.push(len as u64);
+                        self.push_result(code, STATUS_OK);
+                    },
+                    Err(e) => {
+                        self.stack.push(0);
+                        self.push_result(0, io_status(&e));
+                    }
+                }
+            },
+            0x24 => {
+                let addr = self.pop() as usize;
+                let arg = self.pop();
+                let id = self.scheduler.spawn(addr, vec![arg]);
+                self.stack.push(id);
+            },
+            0x25 => self.switch = Some(Switch::Yield),
+            0x26 => {
+                let id = self.pop();
+                if id == self.scheduler.current {
+                    self.trap("deadlock: a task cannot join itself");
+                }
+                if !self.scheduler.exists(id) {
+                    self.trap(&format!("join on task {}, which was never spawned", id));
+                }
+                match self.scheduler.result_of(id) {
+                    Some(result) => self.stack.push(result),
+                    None => {
+                        // put the argument back so the join runs again once
+                        // the task has finished
+                        self.stack.push(id);
+                        self.switch = Some(Switch::Block(Wait::Join(id)));
+                    }
+                }
+            },
+            0x27 => {
+                let capacity = self.pop() as usize;
+                let id = self.scheduler.create_channel(capacity);
+                self.stack.push(id);
+            },
+            0x28 => {
+                let value = self.pop();
+                let id = self.pop();
+                match self.scheduler.channels.get_mut(&id) {
+                    Some(channel) if channel.closed => self.stack.push(STATUS_CLOSED),
+                    Some(channel) if channel.is_full() => {
+                        self.stack.push(id);
+                        self.stack.push(value);
+                        self.switch = Some(Switch::Block(Wait::Send(id)));
+                    },
+                    Some(channel) => {
+                        channel.queue.push_back(value);
+                        self.stack.push(STATUS_OK);
+                    },
+                    None => self.stack.push(STATUS_BAD_HANDLE)
+                }
+            },
+            0x29 | 0x2A => {
+                let id = self.pop();
+                match self.scheduler.channels.get_mut(&id) {
+                    Some(channel) => match channel.queue.pop_front() {
+                        Some(value) => self.push_result(value, STATUS_OK),
+                        None if channel.closed => self.push_result(0, STATUS_CLOSED),
+                        None if syscall == 0x2A => self.push_result(0, STATUS_EMPTY),
+                        None => {
+                            self.stack.push(id);
+                            self.switch = Some(Switch::Block(Wait::Recv(id)));
+                        }
+                    },
+                    None => self.push_result(0, STATUS_BAD_HANDLE)
+                }
+            },
+            0x2B => {
+                let id = self.pop();
+                match self.scheduler.channels.get_mut(&id) {
+                    Some(channel) => {
+                        channel.closed = true;
+                        self.stack.push(STATUS_OK);
+                    },
+                    None => self.stack.push(STATUS_BAD_HANDLE)
+                }
+            },
+            0x2C => {
+                let len = self.pop() as usize;
+                let addr = self.pop() as usize;
+                if self.shared.is_some() {
+                    return self.stack.push(STATUS_ALREADY_EXISTS);
+                }
+                let len = (len + 7) & !7;
+                if !addr.is_multiple_of(8) || len == 0 || addr + len > self.memory.len() {
+                    return self.stack.push(STATUS_INVALID);
+                }
+                self.shared = Some(Arc::new(SharedRegion::new(addr, &self.memory[addr..addr + len])));
+                self.stack.push(STATUS_OK);
+            },
+            0x2D => {
+                let addr = self.pop() as usize;
+                let arg = self.pop();
+                let id = self.spawn_thread(addr, arg, rom);
+                self.stack.push(id);
+            },
+            0x2E => {
+                let handle = self.pop();
+                if !matches!(self.handles.get(handle), Some(Handle::Thread(_))) {
+                    return self.push_result(0, STATUS_BAD_HANDLE);
+                }
+                match self.handles.remove(handle) {
+                    Some(Handle::Thread(thread)) => match thread.join() {
+                        Ok(result) => self.push_result(result, STATUS_OK),
+                        Err(_) => self.push_result(0, STATUS_OTHER)
+                    },
+                    _ => self.push_result(0, STATUS_BAD_HANDLE)
+                }
+            },
+            0x2F => {
+                let addr = self.pop() as usize;
+                let irq = self.pop() as usize;
+                if irq >= VECTORS {
+                    return self.stack.push(STATUS_INVALID);
+                }
+                if irq == IRQ_SIGNAL {
+                    catch_interrupt(addr != 0);
+                }
+                self.vectors[irq] = addr;
+                self.stack.push(STATUS_OK);
+            },
+            0x30 => {
+                let period = self.pop();
+                let mode = self.pop();
+                match Timer::new(mode, period) {
+                    Some(timer) => {
+                        self.timer = timer;
+                        self.stack.push(STATUS_OK);
+                    },
+                    None => self.stack.push(STATUS_INVALID)
+                }
+            },
+            _    => self.fault(EXC_BAD_SYSCALL, &format!("unrecognized SYSCALL {:#x}. Perhaps you're missing an extension?", syscall))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the fault `f` raised, if any.
+    fn fault_of(f: impl FnOnce()) -> Option<Fault> {
+        panic::catch_unwind(AssertUnwindSafe(f))
+            .err()
+            .map(|payload| *payload.downcast::<Fault>().unwrap())
+    }
+
+    // runs `syscall` with `args` pushed in order and returns the fault it
+    // raised, if any.
+    fn syscall_fault(cpu: &mut VMLCpu, syscall: usize, args: &[u64]) -> Option<Fault> {
+        cpu.stack.extend_from_slice(args);
+        fault_of(|| cpu.handle_syscalls(syscall, &Vec::new()))
+    }
+
+    #[test]
+    fn accesses_outside_of_memory_are_faults() {
+        let mut cpu = VMLCpu::new();
+        let end = cpu.memory.len();
+        assert_eq!(fault_of(|| { cpu.load(end - 4, 8); }).map(|f| f.code), Some(EXC_MEMORY));
+        assert_eq!(fault_of(|| cpu.store(usize::MAX - 8, 8, 1)).map(|f| f.code), Some(EXC_MEMORY));
+        assert_eq!(fault_of(|| { cpu.read_u64(2, &vec![0; 8]); }).map(|f| f.code), Some(EXC_MEMORY));
+        assert!(fault_of(|| cpu.store(end - 8, 8, 1)).is_none());
+        assert_eq!(cpu.load(end - 8, 8), 1);
+    }
+
+    #[test]
+    fn threads_share_the_devices() {
+        let mut cpu = VMLCpu::new();
+        cpu.map_device(AUDIO_BASE, Box::new(Audio::new(8000, String::new())));
+        let mut child = cpu.thread_vm(0x10, 7);
+        assert_eq!((child.pc, child.stack.clone()), (0x10, vec![7]));
+
+        child.store(AUDIO_BASE, 2, 0x1234);
+        child.store(AUDIO_BASE, 2, 0x5678);
+        assert_eq!(cpu.load(AUDIO_BASE + 20, 4), 2);
+        assert_eq!(child.load(AUDIO_BASE + 16, 4), 8000);
+    }
+
+    #[test]
+    fn huge_lengths_trap_instead_of_aborting() {
+        let mut cpu = VMLCpu::new();
+        for syscall in [0x10, 0x20] {
+            for (buffer, len) in [(0, u64::MAX), (0, 1 << 40), (u64::MAX - 1, 4), (134217728 - 4, 8)] {
+                let fault = syscall_fault(&mut cpu, syscall, &[1, buffer, len]).unwrap();
+                assert_eq!(fault.code, EXC_MEMORY);
             }
         }
+        // a length that fits gets as far as the handle
+        assert!(syscall_fault(&mut cpu, 0x10, &[99, 134217728 - 8, 8]).is_none());
+        assert_eq!(cpu.stack, vec![0, STATUS_BAD_HANDLE]);
     }
 }
@@ -0,0 +1,76 @@
+use std::fs;
+use std::io;
+
+// the length of the sample data and the bytes per second, or None when
+// either does not fit in the 32-bit fields of the header.
+fn header_sizes(sample_rate: u32, count: usize) -> Option<(u32, u32)> {
+    let data_len = u32::try_from(count.checked_mul(2)?).ok()?;
+    data_len.checked_add(36)?;
+    Some((data_len, sample_rate.checked_mul(2)?))
+}
+
+// mono 16-bit PCM in a RIFF/WAVE container.
+pub fn write_wav(filename: &str, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let (data_len, byte_rate) = header_sizes(sample_rate, samples.len()).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "too many samples or too high a sample rate for a WAV file")
+    })?;
+    let mut output: Vec<u8> = Vec::with_capacity(44 + samples.len() * 2);
+
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&(36 + data_len).to_le_bytes());
+    output.extend_from_slice(b"WAVE");
+
+    output.extend_from_slice(b"fmt ");
+    output.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());
+    output.extend_from_slice(&sample_rate.to_le_bytes());
+    // bytes per second, bytes per frame, bits per sample
+    output.extend_from_slice(&byte_rate.to_le_bytes());
+    output.extend_from_slice(&2u16.to_le_bytes());
+    output.extend_from_slice(&16u16.to_le_bytes());
+
+    output.extend_from_slice(b"data");
+    output.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        output.extend_from_slice(&sample.to_le_bytes());
+    }
+    fs::write(filename, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn writes_header_and_samples() {
+        let filename = env::temp_dir().join("vml-test-write-wav.wav");
+        let filename = filename.to_str().unwrap();
+        write_wav(filename, 8000, &[0, 1, -1, i16::MAX]).unwrap();
+        let expected: Vec<u8> = vec![
+            0x52, 0x49, 0x46, 0x46, 0x2c, 0x00, 0x00, 0x00, 0x57, 0x41, 0x56, 0x45,
+            0x66, 0x6d, 0x74, 0x20, 0x10, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+            0x40, 0x1f, 0x00, 0x00, 0x80, 0x3e, 0x00, 0x00, 0x02, 0x00, 0x10, 0x00,
+            0x64, 0x61, 0x74, 0x61, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+            0xff, 0xff, 0xff, 0x7f
+        ];
+        assert_eq!(fs::read(filename).unwrap(), expected);
+        fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn sizes_that_overflow_the_header() {
+        assert_eq!(header_sizes(44100, 10), Some((20, 88200)));
+        assert_eq!(header_sizes(u32::MAX / 2, 0), Some((0, u32::MAX - 1)));
+        assert_eq!(header_sizes(u32::MAX / 2 + 1, 0), None);
+        assert_eq!(header_sizes(8000, (u32::MAX as usize - 36) / 2), Some((u32::MAX - 37, 16000)));
+        assert_eq!(header_sizes(8000, (u32::MAX as usize - 36) / 2 + 1), None);
+        assert_eq!(header_sizes(8000, usize::MAX), None);
+
+        let filename = env::temp_dir().join("vml-test-wav-rate.wav");
+        assert!(write_wav(filename.to_str().unwrap(), u32::MAX, &[0]).is_err());
+        assert!(!filename.exists());
+    }
+}